@@ -0,0 +1,137 @@
+use std::fs;
+use std::io::Write;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use base64::{encode_config, decode_config, URL_SAFE_NO_PAD};
+use semver::Version;
+use tempfile::NamedTempFile;
+use super::user_config::UserConfig;
+use super::package_source::{PackageSourceContent, ReleaseCache, SourceCache, CompiledSource, split_cache_key};
+use super::error::{MpmError, Result};
+
+pub const CACHE_DIR_NAME: &str = ".mpm-cache";
+/// Where compiled scripts are kept inside the cache, the dot keeps it from being read as a source
+pub const COMPILED_DIR_NAME: &str = ".compiled";
+/// Where files decompressed from release archives are kept inside the cache
+pub const TREES_DIR_NAME: &str = ".trees";
+
+fn encode_str_to_path_safe_b64(value: String) -> String {
+	encode_config(value, URL_SAFE_NO_PAD)
+}
+
+fn decode_path_safe_b64_to_str(encoded: &str) -> Option<String> {
+	String::from_utf8(decode_config(encoded, URL_SAFE_NO_PAD).ok()?).ok()
+}
+
+/// Loads every cached release under `cache_path`, names that don't decode are skipped rather than failing the load
+pub fn load_source_cache(cache_path: &Path, user_config: UserConfig) -> Result<SourceCache> {
+	let mut releases: ReleaseCache = HashMap::new();
+	if !cache_path.exists(){
+		return Ok(SourceCache::new(releases, Some(cache_path.to_path_buf()), user_config));
+	}
+
+	for dir_entry in fs::read_dir(cache_path).map_err(|e| MpmError::io("read", cache_path, e))? {
+		let dir_entry: fs::DirEntry = dir_entry.map_err(|e| MpmError::io("read", cache_path, e))?;
+		let dir_path: PathBuf = dir_entry.path();
+		if !dir_path.is_dir(){
+			continue;
+		}
+		let dir_name: String = match dir_path.file_name().and_then(|n| n.to_str()).and_then(decode_path_safe_b64_to_str) {
+			Some(dir_name) => dir_name,
+			None => continue,
+		};
+		let source_url_key: PathBuf = PathBuf::from(&dir_name);
+		let (source_url, asset): (PathBuf, Option<String>) = split_cache_key(&source_url_key);
+
+		// println!("dir_name={}", source_url_key.to_str().unwrap());
+
+		let mut version_cache: HashMap<Version, PackageSourceContent> = HashMap::new();
+		for file_entry in fs::read_dir(&dir_path).map_err(|e| MpmError::io("read", &dir_path, e))? {
+			let file_entry: fs::DirEntry = file_entry.map_err(|e| MpmError::io("read", &dir_path, e))?;
+			let file_path: PathBuf = file_entry.path();
+			let version_opt: Option<Version> = file_path.file_stem()
+				.and_then(|n| n.to_str())
+				.and_then(decode_path_safe_b64_to_str)
+				.and_then(|version_name| Version::parse(&version_name).ok());
+			let version: Version = match version_opt {
+				Some(version) if file_path.is_file() => version,
+				_ => continue,
+			};
+			let buffer: Vec<u8> = fs::read(&file_path).map_err(|e| MpmError::io("read", &file_path, e))?;
+
+			let data: bytes::Bytes = bytes::Bytes::from(buffer);
+			version_cache.insert(version.clone(), PackageSourceContent{
+				data,
+				version,
+				source_url: source_url.clone(),
+				asset: asset.clone()
+			});
+			// println!("version_name={}", version_name);
+		}
+		releases.insert(source_url_key, version_cache);
+	}
+	return Ok(SourceCache::new(releases, Some(cache_path.to_path_buf()), user_config));
+}
+
+/// Writes every release in the cache to `cache_path`, one directory per source and one zip per version
+pub fn save_source_cache(cache_path: &Path, source_cache: &SourceCache) -> Result<()> {
+	if !cache_path.exists(){
+		fs::create_dir_all(cache_path).map_err(|e| MpmError::io("create", cache_path, e))?;
+	}
+	for (path_buf, version_cache) in &source_cache.releases {
+		let dir_name: String = encode_str_to_path_safe_b64(path_buf.to_string_lossy().to_string());
+		let sub_dir_path: PathBuf = cache_path.join(dir_name);
+		if !sub_dir_path.exists(){
+			fs::create_dir_all(&sub_dir_path).map_err(|e| MpmError::io("create", &sub_dir_path, e))?;
+		}
+		for (version, psc) in version_cache {
+			let file_name: String = encode_str_to_path_safe_b64(version.to_string());
+			let mut file_name_path: PathBuf = PathBuf::from(file_name);
+			file_name_path.set_extension("zip");
+			let full_file_path: PathBuf = sub_dir_path.join(file_name_path);
+			fs::write(&full_file_path, &psc.data).map_err(|e| MpmError::io("write", &full_file_path, e))?;
+		}
+	}
+	return Ok(());
+}
+
+fn get_compiled_path(cache_path: &Path, key_hash: &str) -> PathBuf {
+	return cache_path.join(COMPILED_DIR_NAME).join(format!("{}.json", key_hash));
+}
+
+/// Reads a script compiled earlier under `key_hash`, one that can't be read back is treated as never having been compiled
+pub fn load_compiled(cache_path: &Path, key_hash: &str) -> Option<CompiledSource> {
+	let contents: String = fs::read_to_string(get_compiled_path(cache_path, key_hash)).ok()?;
+	return serde_json::from_str(&contents).ok();
+}
+
+pub fn save_compiled(cache_path: &Path, key_hash: &str, compiled_source: &CompiledSource) -> Result<()> {
+	let compiled_path: PathBuf = get_compiled_path(cache_path, key_hash);
+	if let Some(parent) = compiled_path.parent() {
+		fs::create_dir_all(parent).map_err(|e| MpmError::io("create", parent, e))?;
+	}
+	let contents: String = serde_json::to_string(compiled_source).expect("compiled sources always serialize");
+	fs::write(&compiled_path, contents).map_err(|e| MpmError::io("write", &compiled_path, e))?;
+	return Ok(());
+}
+
+/// Where the files decompressed from an archive are kept, named by its hash so a replaced archive never reads the old one's files
+pub fn get_tree_path(cache_path: &Path, archive_hash: &blake3::Hash) -> PathBuf {
+	return cache_path.join(TREES_DIR_NAME).join(archive_hash.to_hex().as_str());
+}
+
+/// Reads a file decompressed by an earlier run, `None` if there's none to read
+pub fn load_tree_file(tree_path: &Path, file_key: &str) -> Option<Vec<u8>> {
+	return fs::read(tree_path.join(file_key)).ok();
+}
+
+/// Keeps a decompressed file, written through a temporary file so a run stopped halfway can't leave part of one behind
+pub fn save_tree_file(tree_path: &Path, file_key: &str, contents: &[u8]) -> Result<()> {
+	let file_path: PathBuf = tree_path.join(file_key);
+	let dir_path: &Path = file_path.parent().unwrap_or(tree_path);
+	fs::create_dir_all(dir_path).map_err(|e| MpmError::io("create", dir_path, e))?;
+	let mut temp_file: NamedTempFile = NamedTempFile::new_in(dir_path).map_err(|e| MpmError::io("create", dir_path, e))?;
+	temp_file.write_all(contents).map_err(|e| MpmError::io("write", temp_file.path().to_path_buf(), e))?;
+	temp_file.persist(&file_path).map_err(|e| MpmError::io("write", &file_path, e.error))?;
+	return Ok(());
+}
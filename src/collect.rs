@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use walkdir::WalkDir;
+use super::package::FILE_NAME_STRING;
+use super::diagnostic::suggest;
+use super::error::{MpmError, Result};
+
+/// Gitignore-style file listing what to leave out of a package, read in every directory alongside any `.gitignore`
+pub const IGNORE_FILE_NAME: &str = ".mpmignore";
+pub const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", IGNORE_FILE_NAME];
+/// What's compiled when nothing says otherwise
+pub const DEFAULT_INCLUDE: &str = "**/*.cs";
+
+/// Which files under a package's directory get compiled, as globs matched against each file's path relative to it
+#[derive(Debug, Clone, Default)]
+pub struct ScriptFilter {
+	/// Empty means `DEFAULT_INCLUDE`
+	pub include: Vec<String>,
+	pub exclude: Vec<String>,
+}
+
+impl ScriptFilter {
+	/// Lets the globs given on the command line replace the manifest's, each list on its own
+	pub fn overridden_by(self: Self, include: Vec<String>, exclude: Vec<String>) -> Self {
+		return ScriptFilter {
+			include: if include.is_empty() { self.include } else { include },
+			exclude: if exclude.is_empty() { self.exclude } else { exclude },
+		};
+	}
+}
+
+/// An optional part of a package, declared under its manifest's `[features]` and compiled only for dependencies listing it
+#[derive(Debug, Clone, Default)]
+pub struct Feature {
+	/// Globs of the files only compiled with this feature, relative to the package like `include`
+	pub files: Vec<String>,
+	/// Symbols `#define`d at the top of the script when it's selected
+	pub defines: Vec<String>,
+}
+
+/// Checks a `#define` symbol, the error being why it isn't one
+pub fn validate_define(symbol: &str) -> std::result::Result<(), String> {
+	let mut chars = symbol.chars();
+	let is_identifier: bool = chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+	if !is_identifier {
+		return Err(String::from("symbols are letters, digits and underscores, not starting with a digit"));
+	}
+	if symbol == "true" || symbol == "false" {
+		return Err(String::from("it's a reserved word"));
+	}
+	return Ok(());
+}
+
+/// Leaves out the files of every feature that wasn't selected, returning the sorted `#define` symbols of those that were.
+/// Files no feature claims are always kept, and a file claimed by a selected feature is kept even if another claims it too
+pub fn select_features(scripts: &mut HashMap<String, String>, features: &BTreeMap<String, Feature>, selected: &[String], source_url: &str) -> Result<Vec<String>> {
+	for feature_name in selected {
+		if !features.contains_key(feature_name) {
+			let names: Vec<&str> = features.keys().map(|name| name.as_str()).collect();
+			return Err(MpmError::UnknownFeature {
+				source_url: source_url.to_string(),
+				feature: feature_name.clone(),
+				available: names.iter().map(|name| name.to_string()).collect(),
+				suggestion: suggest(feature_name, &names).map(|name| name.to_string()),
+			});
+		}
+	}
+
+	let mut selected_globs: Vec<String> = Vec::new();
+	let mut unselected_globs: Vec<String> = Vec::new();
+	let mut defines: Vec<String> = Vec::new();
+	for (feature_name, feature) in features {
+		match selected.contains(feature_name) {
+			true => {
+				selected_globs.extend(feature.files.iter().cloned());
+				defines.extend(feature.defines.iter().cloned());
+			},
+			false => unselected_globs.extend(feature.files.iter().cloned()),
+		}
+	}
+	let selected_set: GlobSet = build_glob_set(&selected_globs)?;
+	let unselected_set: GlobSet = build_glob_set(&unselected_globs)?;
+	scripts.retain(|relative_key, _| selected_set.is_match(relative_key) || !unselected_set.is_match(relative_key));
+
+	defines.sort();
+	defines.dedup();
+	return Ok(defines);
+}
+
+/// Checks a single glob, the error being why it isn't one
+pub fn validate_glob(pattern: &str) -> std::result::Result<(), String> {
+	return Glob::new(pattern).map(|_| ()).map_err(|e| e.kind().to_string());
+}
+
+pub fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+	let mut builder: GlobSetBuilder = GlobSetBuilder::new();
+	for pattern in patterns {
+		let glob: Glob = Glob::new(pattern).map_err(|e| MpmError::InvalidGlob {
+			pattern: pattern.clone(),
+			message: e.kind().to_string(),
+		})?;
+		builder.add(glob);
+	}
+	return builder.build().map_err(|e| MpmError::InvalidGlob {
+		pattern: patterns.join(", "),
+		message: e.to_string(),
+	});
+}
+
+/// Forward slashes on every platform, so keys and their order don't change with the OS
+pub fn to_relative_key(relative_path: &Path) -> String {
+	return relative_path.components()
+		.map(|component| component.as_os_str().to_string_lossy().to_string())
+		.collect::<Vec<String>>()
+		.join("/");
+}
+
+/// Whether a file, given by its path relative to the package, lists what to ignore
+pub fn is_ignore_file(relative_key: &str) -> bool {
+	let file_name: &str = relative_key.rsplit('/').next().unwrap_or(relative_key);
+	return IGNORE_FILE_NAMES.contains(&file_name);
+}
+
+/// Decides which of a package's files are compiled, the same way whether they're on disk or still in an archive
+pub struct ScriptSelector {
+	include_set: GlobSet,
+	exclude_set: GlobSet,
+	/// Each ignore file's matcher by the directory it sits in, shallowest first
+	ignores: Vec<(String, Gitignore)>,
+}
+
+impl ScriptSelector {
+	/// `ignore_files` are the package's ignore files as their relative path and contents
+	pub fn new(filter: &ScriptFilter, ignore_files: &[(String, String)]) -> Result<Self> {
+		let include_patterns: Vec<String> = match filter.include.is_empty() {
+			true => vec![DEFAULT_INCLUDE.to_string()],
+			false => filter.include.clone(),
+		};
+		let mut ignores: Vec<(String, Gitignore)> = Vec::new();
+		for (relative_key, contents) in ignore_files {
+			let dir_key: String = relative_key.rsplit_once('/').map(|(dir_key, _)| dir_key.to_string()).unwrap_or_default();
+			let mut builder: GitignoreBuilder = GitignoreBuilder::new(&dir_key);
+			for line in contents.lines() {
+				// a bad line in someone else's ignore file shouldn't stop their package compiling
+				let _ = builder.add_line(None, line);
+			}
+			if let Ok(gitignore) = builder.build() {
+				ignores.push((dir_key, gitignore));
+			}
+		}
+		ignores.sort_by_key(|(dir_key, _)| match dir_key.is_empty() {
+			true => 0,
+			false => dir_key.matches('/').count() + 1,
+		});
+		return Ok(ScriptSelector {
+			include_set: build_glob_set(&include_patterns)?,
+			exclude_set: build_glob_set(&filter.exclude)?,
+			ignores,
+		});
+	}
+
+	fn is_ignored(self: &Self, relative_key: &str) -> bool {
+		// the deepest ignore file with an opinion wins, like git
+		for (dir_key, gitignore) in self.ignores.iter().rev() {
+			let is_inside: bool = dir_key.is_empty() || relative_key.starts_with(&format!("{}/", dir_key));
+			if !is_inside {
+				continue;
+			}
+			let matched = gitignore.matched_path_or_any_parents(relative_key, false);
+			if matched.is_ignore() {
+				return true;
+			}
+			if matched.is_whitelist() {
+				return false;
+			}
+		}
+		return false;
+	}
+
+	pub fn is_selected(self: &Self, relative_key: &str) -> bool {
+		// hidden files and folders are left out, as are the package's own manifest
+		if relative_key.split('/').any(|component| component.starts_with('.')) || relative_key == FILE_NAME_STRING {
+			return false;
+		}
+		if !self.include_set.is_match(relative_key) || self.exclude_set.is_match(relative_key) {
+			return false;
+		}
+		return !self.is_ignored(relative_key);
+	}
+}
+
+/// Reads every file under `root_dir` that the filter selects and no ignore file excludes, keyed by its path relative to `root_dir`.
+/// Files in `skipped_paths`, like a build's own output, are left out
+pub fn collect_scripts(root_dir: &Path, filter: &ScriptFilter, skipped_paths: &[PathBuf]) -> Result<HashMap<String, String>> {
+	let skipped_paths: Vec<PathBuf> = skipped_paths.iter()
+		.map(|path| fs::canonicalize(path).unwrap_or(path.clone()))
+		.collect();
+
+	let mut file_paths: Vec<(String, PathBuf)> = Vec::new();
+	let mut ignore_files: Vec<(String, String)> = Vec::new();
+	// hidden folders like .git are never compiled, no need to look inside them
+	let is_hidden_dir = |entry: &walkdir::DirEntry| entry.depth() > 0 && entry.file_type().is_dir() && entry.file_name().to_string_lossy().starts_with('.');
+	for entry in WalkDir::new(root_dir).into_iter().filter_entry(|entry| !is_hidden_dir(entry)) {
+		let entry: walkdir::DirEntry = entry.map_err(|e| MpmError::io("read", root_dir, e.into()))?;
+		if !entry.file_type().is_file() {
+			continue;
+		}
+		let path: &Path = entry.path();
+		let relative_key: String = to_relative_key(path.strip_prefix(root_dir).unwrap_or(path));
+		if is_ignore_file(&relative_key) {
+			let contents: String = fs::read_to_string(path).map_err(|e| MpmError::io("read", path, e))?;
+			ignore_files.push((relative_key.clone(), contents));
+		}
+		file_paths.push((relative_key, path.to_path_buf()));
+	}
+
+	let selector: ScriptSelector = ScriptSelector::new(filter, &ignore_files)?;
+	let mut scripts: HashMap<String, String> = HashMap::new();
+	for (relative_key, path) in file_paths {
+		if !selector.is_selected(&relative_key) {
+			continue;
+		}
+		if skipped_paths.iter().any(|skipped_path| fs::canonicalize(&path).map(|p| p == *skipped_path).unwrap_or(false)) {
+			continue;
+		}
+		let contents: String = fs::read_to_string(&path).map_err(|e| MpmError::io("read", &path, e))?;
+		scripts.insert(relative_key, contents);
+	}
+	return Ok(scripts);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn write_files(root_dir: &Path, files: &[(&str, &str)]) {
+		for (relative_key, contents) in files {
+			let path: PathBuf = root_dir.join(relative_key);
+			fs::create_dir_all(path.parent().expect("a file has a parent")).expect("the parent dir to be created");
+			fs::write(&path, contents).expect("the file to be written");
+		}
+	}
+
+	fn collected_keys(root_dir: &Path, filter: &ScriptFilter) -> Vec<String> {
+		let scripts: HashMap<String, String> = collect_scripts(root_dir, filter, &[]).expect("the scripts to be collected");
+		let mut keys: Vec<String> = scripts.into_keys().collect();
+		keys.sort();
+		return keys;
+	}
+
+	#[test]
+	fn leaves_out_gitignored_files() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		write_files(temp_dir.path(), &[
+			(".gitignore", "Generated/\n"),
+			("Main.cs", ""),
+			("Generated/Bindings.cs", ""),
+			("Sub/Util.cs", ""),
+		]);
+		assert_eq!(collected_keys(temp_dir.path(), &ScriptFilter::default()), vec!["Main.cs", "Sub/Util.cs"]);
+	}
+
+	#[test]
+	fn deeper_mpmignore_negation_wins() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		write_files(temp_dir.path(), &[
+			(".mpmignore", "*.Test.cs\n"),
+			("Main.Test.cs", ""),
+			("Kept/.mpmignore", "!Shared.Test.cs\n"),
+			("Kept/Shared.Test.cs", ""),
+			("Kept/Other.Test.cs", ""),
+		]);
+		assert_eq!(collected_keys(temp_dir.path(), &ScriptFilter::default()), vec!["Kept/Shared.Test.cs"]);
+	}
+
+	#[test]
+	fn skips_hidden_files_and_folders() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		write_files(temp_dir.path(), &[
+			("Main.cs", ""),
+			(".Hidden.cs", ""),
+			(".git/Stray.cs", ""),
+			("Sub/.Secret/Inner.cs", ""),
+		]);
+		assert_eq!(collected_keys(temp_dir.path(), &ScriptFilter::default()), vec!["Main.cs"]);
+	}
+
+	#[test]
+	fn filter_globs_apply_to_relative_paths() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		write_files(temp_dir.path(), &[
+			("Main.cs", ""),
+			("README.md", ""),
+			("Editor/Tool.cs", ""),
+			("Src/Lib.cs", ""),
+		]);
+		let filter: ScriptFilter = ScriptFilter {
+			include: Vec::new(),
+			exclude: vec![String::from("Editor/**")],
+		};
+		assert_eq!(collected_keys(temp_dir.path(), &filter), vec!["Main.cs", "Src/Lib.cs"]);
+	}
+
+	fn ui_features() -> BTreeMap<String, Feature> {
+		let mut features: BTreeMap<String, Feature> = BTreeMap::new();
+		features.insert(String::from("tween"), Feature {
+			files: vec![String::from("Tween/**"), String::from("Shared/Easing.cs")],
+			defines: vec![String::from("UI_TWEEN")],
+		});
+		features.insert(String::from("spring"), Feature {
+			files: vec![String::from("Spring/**"), String::from("Shared/Easing.cs")],
+			defines: vec![String::from("UI_SPRING"), String::from("UI_TWEEN")],
+		});
+		return features;
+	}
+
+	fn ui_scripts() -> HashMap<String, String> {
+		return ["Button.cs", "Tween/Tween.cs", "Spring/Spring.cs", "Shared/Easing.cs"].iter()
+			.map(|relative_key| (relative_key.to_string(), String::new()))
+			.collect();
+	}
+
+	#[test]
+	fn keeps_only_the_selected_features_files() {
+		let mut scripts: HashMap<String, String> = ui_scripts();
+		let defines: Vec<String> = select_features(&mut scripts, &ui_features(), &[String::from("tween")], "ui").expect("the features to be selected");
+		let mut keys: Vec<String> = scripts.into_keys().collect();
+		keys.sort();
+		assert_eq!(keys, vec!["Button.cs", "Shared/Easing.cs", "Tween/Tween.cs"]);
+		assert_eq!(defines, vec!["UI_TWEEN"]);
+	}
+
+	#[test]
+	fn unselected_features_drop_files_only_they_claim() {
+		let mut scripts: HashMap<String, String> = ui_scripts();
+		let defines: Vec<String> = select_features(&mut scripts, &ui_features(), &[], "ui").expect("no features to be selected");
+		assert_eq!(scripts.into_keys().collect::<Vec<String>>(), vec!["Button.cs"]);
+		assert!(defines.is_empty());
+	}
+
+	#[test]
+	fn selected_features_merge_their_defines() {
+		let mut scripts: HashMap<String, String> = ui_scripts();
+		let selected: Vec<String> = vec![String::from("spring"), String::from("tween")];
+		let defines: Vec<String> = select_features(&mut scripts, &ui_features(), &selected, "ui").expect("the features to be selected");
+		assert_eq!(scripts.len(), 4);
+		assert_eq!(defines, vec!["UI_SPRING", "UI_TWEEN"]);
+	}
+
+	#[test]
+	fn unknown_feature_suggests_a_close_one() {
+		let mut scripts: HashMap<String, String> = ui_scripts();
+		match select_features(&mut scripts, &ui_features(), &[String::from("twen")], "ui") {
+			Err(MpmError::UnknownFeature { feature, available, suggestion, .. }) => {
+				assert_eq!(feature, "twen");
+				assert_eq!(available, vec!["spring", "tween"]);
+				assert_eq!(suggestion.as_deref(), Some("tween"));
+			},
+			other => panic!("expected an unknown feature error, got {:?}", other),
+		}
+		assert_eq!(scripts.len(), 4);
+	}
+
+	#[test]
+	fn validates_define_symbols() {
+		for symbol in ["UI_TWEEN", "_private", "Debug2"] {
+			assert!(validate_define(symbol).is_ok(), "{} should be valid", symbol);
+		}
+		for symbol in ["", "2D", "UI-TWEEN", "UI TWEEN", "true", "false"] {
+			assert!(validate_define(symbol).is_err(), "{} should be invalid", symbol);
+		}
+	}
+}
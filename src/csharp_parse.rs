@@ -1,6 +1,46 @@
 use std::collections::HashMap;
 use regex::Regex;
 use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the same scripts and options compile to something different, so outputs cached by the old combiner aren't reused.
+/// The mpm version in the cache key can't tell builds in between releases apart
+pub const COMPILER_FINGERPRINT: u32 = 1;
+
+/// Settings that change what the same scripts compile to, set in `mpm.toml` and overridden by a myth's `[compiler]`.
+/// They're part of every compiled-output cache key so changing one leaves old outputs behind
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompileOptions {
+	/// Comment written below mpm's own header, like a license notice. Each line becomes its own `//` line
+	pub header: Option<String>,
+	/// Symbols `#define`d at the top of every script, along with those of a dependency's selected features
+	pub defines: Vec<String>,
+}
+
+impl CompileOptions {
+	/// Lets a myth's options replace the workspace's, each one on its own
+	pub fn overridden_by(self: Self, other: CompileOptions) -> Self {
+		return CompileOptions {
+			header: other.header.or(self.header),
+			defines: if other.defines.is_empty() { self.defines } else { other.defines },
+		};
+	}
+
+	/// Adds the symbols of a dependency's selected features
+	pub fn with_defines(mut self: Self, defines: Vec<String>) -> Self {
+		self.defines.extend(defines);
+		self.defines.sort();
+		self.defines.dedup();
+		return self;
+	}
+
+	/// Stable hash of the options and `COMPILER_FINGERPRINT`, for cache keys
+	pub fn fingerprint(self: &Self) -> String {
+		let options_json: String = serde_json::to_string(self).unwrap_or_default();
+		return blake3::hash(format!("{}\0{}", COMPILER_FINGERPRINT, options_json).as_bytes()).to_hex().to_string();
+	}
+}
 
 
 fn get_matching_lines(input: &str, pattern: &Regex) -> Vec<String> {
@@ -62,11 +102,12 @@ fn get_matching_lines(input: &str, pattern: &Regex) -> Vec<String> {
 pub fn compile_to_single_script(
 	header_comment: String,
 	target_namespace: String, 
-	dependency_scripts: HashMap<String, String>
+	dependency_scripts: HashMap<String, String>,
+	options: &CompileOptions
 ) -> String{
 	
 	let mut keys: Vec<String> = Vec::new();
-	for (dep_name, _dep_value) in &dependency_scripts {
+	for dep_name in dependency_scripts.keys() {
 		keys.insert(keys.len(), dep_name.clone());
 	}
 	keys.sort();
@@ -127,5 +168,11 @@ pub fn compile_to_single_script(
 	// 	mega_script = mega_script.replace(&key.replace("namespace ", "using "), &format!("using {}",value));
 	// }
 	
-	return format!("// {}\n{}", header_comment, mega_script);
+	// the custom header goes after mpm's so `parse_source_header` still finds it near the top
+	let custom_header: String = options.header.as_deref()
+		.map(|header| header.lines().map(|line| format!("\n// {}", line)).collect::<String>())
+		.unwrap_or_default();
+	// C# wants every #define before the first using
+	let define_lines: String = options.defines.iter().map(|symbol| format!("\n#define {}", symbol)).collect();
+	return format!("// {}{}{}\n{}", header_comment, custom_header, define_lines, mega_script);
 }
\ No newline at end of file
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+
+// tabs are expanded so the carets line up with what's printed above them
+const TAB_WIDTH: usize = 4;
+
+/// Where in a file a diagnostic points
+#[derive(Debug, Clone)]
+pub struct SourceLocation {
+	/// 1 based
+	pub line: usize,
+	/// 1 based, counted in characters
+	pub column: usize,
+	pub line_text: String,
+	/// How many characters of `line_text` the carets cover
+	pub width: usize,
+}
+
+impl SourceLocation {
+	/// Resolves a byte span in `contents`, spans running past the end of their line are cut at the line end
+	pub fn from_span(contents: &str, span: Range<usize>) -> Self {
+		let start: usize = floor_char_boundary(contents, span.start.min(contents.len()));
+		let end: usize = floor_char_boundary(contents, span.end.clamp(start, contents.len()));
+
+		let line_start: usize = contents[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+		let line_end: usize = contents[start..].find('\n').map(|i| i + start).unwrap_or(contents.len());
+		let line_text: String = contents[line_start..line_end].trim_end_matches('\r').to_string();
+
+		let line: usize = contents[..start].matches('\n').count() + 1;
+		let column: usize = contents[line_start..start].chars().count() + 1;
+		let width: usize = contents[start..end.min(line_end)].chars().count().max(1);
+
+		return SourceLocation {
+			line,
+			column,
+			line_text,
+			width,
+		};
+	}
+}
+
+fn floor_char_boundary(contents: &str, mut index: usize) -> usize {
+	while !contents.is_char_boundary(index) {
+		index -= 1;
+	}
+	return index;
+}
+
+/// A problem in a file, rendered like rustc and cargo do
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub path: PathBuf,
+	pub message: String,
+	/// Dotted path of the key at fault, e.g. `dependencies.Signal`
+	pub key: Option<String>,
+	pub location: Option<SourceLocation>,
+	pub help: Option<String>,
+}
+
+impl Diagnostic {
+	pub fn new(path: impl Into<PathBuf>, message: impl Into<String>) -> Self {
+		return Diagnostic {
+			path: path.into(),
+			message: message.into(),
+			key: None,
+			location: None,
+			help: None,
+		};
+	}
+
+	pub fn with_span(mut self: Self, contents: &str, span_opt: Option<Range<usize>>) -> Self {
+		self.location = span_opt.map(|span| SourceLocation::from_span(contents, span));
+		return self;
+	}
+
+	pub fn with_key(mut self: Self, key: impl Into<String>) -> Self {
+		self.key = Some(key.into());
+		return self;
+	}
+
+	pub fn with_help(mut self: Self, help_opt: Option<String>) -> Self {
+		self.help = help_opt;
+		return self;
+	}
+}
+
+fn expand_tabs(text: &str) -> String {
+	return text.replace('\t', &" ".repeat(TAB_WIDTH));
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.key {
+			Some(key) => writeln!(f, "error: {} (at `{}`)", self.message, key)?,
+			None => writeln!(f, "error: {}", self.message)?,
+		}
+		match &self.location {
+			Some(location) => {
+				let gutter: String = " ".repeat(location.line.to_string().len());
+				writeln!(f, "{}--> {}:{}:{}", gutter, self.path.display(), location.line, location.column)?;
+				writeln!(f, "{} |", gutter)?;
+				writeln!(f, "{} | {}", location.line, expand_tabs(&location.line_text))?;
+
+				let before_text: String = location.line_text.chars().take(location.column - 1).collect();
+				let caret_offset: usize = expand_tabs(&before_text).chars().count();
+				write!(f, "{} | {}{}", gutter, " ".repeat(caret_offset), "^".repeat(location.width))?;
+				if let Some(help) = &self.help {
+					write!(f, "\n{} = help: {}", gutter, help)?;
+				}
+			},
+			None => {
+				write!(f, " --> {}", self.path.display())?;
+				if let Some(help) = &self.help {
+					write!(f, "\n  = help: {}", help)?;
+				}
+			},
+		}
+		return Ok(());
+	}
+}
+
+/// Picks the candidate closest to a misspelt `word`, if any is close enough to be a likely typo
+pub fn suggest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+	let lower_word: String = word.to_lowercase();
+	let mut best_opt: Option<(&'a str, usize)> = None;
+	for candidate in candidates {
+		let distance: usize = strsim::damerau_levenshtein(&lower_word, &candidate.to_lowercase());
+		// short keys only get one typo of slack, longer ones get a bit more
+		let max_distance: usize = if candidate.len() <= 4 { 1 } else { 3 };
+		let is_better: bool = best_opt.map(|(_, best_distance)| distance < best_distance).unwrap_or(true);
+		if distance <= max_distance && is_better {
+			best_opt = Some((candidate, distance));
+		}
+	}
+	return best_opt.map(|(candidate, _)| candidate);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn span_resolves_to_its_line_and_column() {
+		let contents: &str = "[dependencies]\nSignal = \"x\"\n";
+		let start: usize = contents.find("\"x\"").expect("the value is in the contents");
+		let location: SourceLocation = SourceLocation::from_span(contents, start..start + 3);
+		assert_eq!(location.line, 2);
+		assert_eq!(location.column, 10);
+		assert_eq!(location.line_text, "Signal = \"x\"");
+		assert_eq!(location.width, 3);
+	}
+
+	#[test]
+	fn span_on_a_last_line_without_a_newline() {
+		let contents: &str = "namespace = \"A\"\nnmespace = \"B\"";
+		let start: usize = contents.find("nmespace").expect("the key is in the contents");
+		let location: SourceLocation = SourceLocation::from_span(contents, start..contents.len() + 10);
+		assert_eq!(location.line, 2);
+		assert_eq!(location.column, 1);
+		assert_eq!(location.line_text, "nmespace = \"B\"");
+		assert_eq!(location.width, location.line_text.chars().count());
+	}
+
+	#[test]
+	fn carets_count_characters_not_bytes() {
+		let contents: &str = "name = \"Señal\"\n";
+		let start: usize = contents.find("Señal").expect("the value is in the contents");
+		let location: SourceLocation = SourceLocation::from_span(contents, start..start + "Señal".len());
+		assert_eq!(location.column, 9);
+		assert_eq!(location.width, 5);
+
+		let rendered: String = Diagnostic::new("mpm.toml", "bad").with_span(contents, Some(start..start + "Señal".len())).to_string();
+		assert!(rendered.ends_with("  |         ^^^^^"), "carets should sit under the value:\n{}", rendered);
+	}
+
+	#[test]
+	fn carets_line_up_after_tabs() {
+		let contents: &str = "\turl = 1\n";
+		let start: usize = contents.find('1').expect("the value is in the contents");
+		let rendered: String = Diagnostic::new("mpm.toml", "bad")
+			.with_span(contents, Some(start..start + 1))
+			.with_help(Some(String::from("use a string")))
+			.to_string();
+		let lines: Vec<&str> = rendered.lines().collect();
+		assert_eq!(lines[1], " --> mpm.toml:1:8");
+		assert_eq!(lines[3], "1 |     url = 1");
+		assert_eq!(lines[4], "  |           ^");
+		assert_eq!(lines[5], "  = help: use a string");
+	}
+
+	#[test]
+	fn suggests_close_candidates_only() {
+		let candidates: [&str; 3] = ["namespace", "include", "url"];
+		assert_eq!(suggest("nmespace", &candidates), Some("namespace"));
+		assert_eq!(suggest("Include", &candidates), Some("include"));
+		assert_eq!(suggest("ulr", &candidates), Some("url"));
+		assert_eq!(suggest("uri", &candidates), Some("url"));
+		assert_eq!(suggest("path", &candidates), None);
+		assert_eq!(suggest("dependencies", &candidates), None);
+	}
+}
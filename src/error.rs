@@ -0,0 +1,247 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+use super::diagnostic::Diagnostic;
+
+pub type Result<T> = std::result::Result<T, MpmError>;
+
+/// Why an archive was refused while unpacking it
+#[derive(Debug, Error)]
+pub enum ArchiveViolation {
+	#[error("it holds more than {limit} files")]
+	TooManyFiles {
+		limit: usize,
+	},
+	#[error("it unpacks to more than {limit} bytes")]
+	TooLarge {
+		limit: u64,
+	},
+	#[error("'{entry}' unpacks to more than {limit} bytes")]
+	FileTooLarge {
+		entry: String,
+		limit: u64,
+	},
+	#[error("'{entry}' points outside of the archive")]
+	UnsafePath {
+		entry: String,
+	},
+	#[error("'{entry}' is a symlink")]
+	Symlink {
+		entry: String,
+	},
+	#[error("'{entry}' is a device or other special file")]
+	SpecialFile {
+		entry: String,
+	},
+}
+
+/// Everything that can go wrong inside libmuse, each variant carries enough context to be reported on its own
+#[derive(Debug, Error)]
+pub enum MpmError {
+	#[error("couldn't {action} '{}': {source}", path.display())]
+	Io {
+		action: &'static str,
+		path: PathBuf,
+		#[source]
+		source: io::Error,
+	},
+	#[error("no {file_name} found in '{}'", dir.display())]
+	PackageNotFound {
+		dir: PathBuf,
+		file_name: &'static str,
+	},
+	#[error("no myth named '{name}' found under '{}'{}", dir.display(), suggestion.as_ref().map(|name| format!(", did you mean '{}'?", name)).unwrap_or_default())]
+	MythNotFound {
+		name: String,
+		dir: PathBuf,
+		suggestion: Option<String>,
+	},
+	#[error("more than one myth is named '{name}', give its directory instead: {}", paths.iter().map(|path| format!("'{}'", path.display())).collect::<Vec<String>>().join(", "))]
+	AmbiguousMyth {
+		name: String,
+		paths: Vec<PathBuf>,
+	},
+	#[error("'{source_url}' has no feature named '{feature}'{}", match (suggestion, available.is_empty()) {
+		(Some(name), _) => format!(", did you mean '{}'?", name),
+		(None, true) => String::from(", it doesn't declare any"),
+		(None, false) => format!(", it has [{}]", available.join(", ")),
+	})]
+	UnknownFeature {
+		source_url: String,
+		feature: String,
+		available: Vec<String>,
+		suggestion: Option<String>,
+	},
+	#[error("{} manifest problem{} found", diagnostics.len(), if diagnostics.len() == 1 { "" } else { "s" })]
+	Manifest {
+		diagnostics: Vec<Diagnostic>,
+	},
+	#[error("'{}' already exists", path.display())]
+	ManifestExists {
+		path: PathBuf,
+	},
+	#[error("no dependency named '{name}' in '{}'", path.display())]
+	DependencyNotFound {
+		path: PathBuf,
+		name: String,
+	},
+	#[error("bad user config '{}': {message}", path.display())]
+	UserConfig {
+		path: PathBuf,
+		message: String,
+	},
+	#[error("bad workspace config '{}': {message}", path.display())]
+	WorkspaceConfig {
+		path: PathBuf,
+		message: String,
+	},
+	#[error("'{url}' is not a valid dependency URL: {reason}")]
+	InvalidUrl {
+		url: String,
+		reason: String,
+	},
+	#[error("bad version requirement '{requirement}' in '{url}': {source}")]
+	InvalidVersionReq {
+		url: String,
+		requirement: String,
+		#[source]
+		source: semver::Error,
+	},
+	#[error("unknown source host '{host}', add it under [hosts] in ~/.mpm/config.toml")]
+	UnknownHost {
+		host: String,
+	},
+	#[error("GitHub request for '{source_url}' failed: {message}")]
+	GitHub {
+		source_url: String,
+		message: String,
+	},
+	#[error("GitHub API rate limit exceeded for '{source_url}'{}{hint}", reset.as_ref().map(|reset| format!(", it resets at {}", reset)).unwrap_or_default())]
+	RateLimited {
+		source_url: String,
+		reset: Option<String>,
+		hint: String,
+	},
+	#[error("download of '{url}' failed: {message}")]
+	Download {
+		url: String,
+		message: String,
+	},
+	#[error("no release of '{source_url}' matches '{version_req}'")]
+	NoMatchingRelease {
+		source_url: String,
+		version_req: String,
+	},
+	#[error("no cached release of '{source_url}' matches '{version_req}'")]
+	NotCached {
+		source_url: String,
+		version_req: String,
+	},
+	#[error("release '{tag}' of '{source_url}' has no asset named '{asset}', it has [{}]", available.join(", "))]
+	AssetNotFound {
+		source_url: String,
+		tag: String,
+		asset: String,
+		available: Vec<String>,
+	},
+	#[error("release '{tag}' of '{source_url}' has no zipball")]
+	MissingZipball {
+		source_url: String,
+		tag: String,
+	},
+	#[error("bad archive from '{source_url}': {message}")]
+	Archive {
+		source_url: String,
+		message: String,
+	},
+	#[error("refused archive from '{source_url}': {violation}")]
+	UnsafeArchive {
+		source_url: String,
+		violation: ArchiveViolation,
+	},
+	#[error("'{inner_path}' doesn't exist in the release of '{source_url}'")]
+	InnerPathNotFound {
+		source_url: String,
+		inner_path: String,
+	},
+	#[error("'{source_url}' is deprecated{}", message.as_ref().map(|message| format!(": {}", message)).unwrap_or_default())]
+	Deprecated {
+		source_url: String,
+		message: Option<String>,
+	},
+	#[error("'{pattern}' is not a valid glob: {message}")]
+	InvalidGlob {
+		pattern: String,
+		message: String,
+	},
+	#[error("path dependency '{}' doesn't exist", path.display())]
+	PathDependencyNotFound {
+		path: PathBuf,
+	},
+	#[error("couldn't watch '{}': {message}", path.display())]
+	Watch {
+		path: PathBuf,
+		message: String,
+	},
+	#[error("{myth}: couldn't satisfy dependency '{dependency}': {source}")]
+	Dependency {
+		myth: String,
+		dependency: String,
+		#[source]
+		source: Box<MpmError>,
+	},
+	#[error("{myth}: {} dependencies couldn't be satisfied", failures.len())]
+	Unsatisfied {
+		myth: String,
+		failures: Vec<MpmError>,
+	},
+}
+
+impl MpmError {
+	pub fn io(action: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+		return MpmError::Io {
+			action,
+			path: path.into(),
+			source,
+		};
+	}
+
+	/// Process exit code for the binary, grouped by who has to act on the error
+	pub fn exit_code(self: &Self) -> i32 {
+		match self {
+			// something in a manifest, config or command line needs fixing
+			MpmError::PackageNotFound { .. }
+			| MpmError::MythNotFound { .. }
+			| MpmError::AmbiguousMyth { .. }
+			| MpmError::UnknownFeature { .. }
+			| MpmError::Manifest { .. }
+			| MpmError::DependencyNotFound { .. }
+			| MpmError::ManifestExists { .. }
+			| MpmError::UserConfig { .. }
+			| MpmError::WorkspaceConfig { .. }
+			| MpmError::InvalidUrl { .. }
+			| MpmError::InvalidVersionReq { .. }
+			| MpmError::UnknownHost { .. }
+			| MpmError::NoMatchingRelease { .. }
+			| MpmError::AssetNotFound { .. }
+			| MpmError::InnerPathNotFound { .. }
+			| MpmError::Deprecated { .. }
+			| MpmError::PathDependencyNotFound { .. }
+			| MpmError::InvalidGlob { .. } => 2,
+			// the network or GitHub let us down, retrying later may work
+			MpmError::GitHub { .. }
+			| MpmError::RateLimited { .. }
+			| MpmError::Download { .. }
+			| MpmError::MissingZipball { .. } => 3,
+			// offline and the cache can't cover it
+			MpmError::NotCached { .. } => 4,
+			MpmError::Io { .. }
+			| MpmError::Archive { .. }
+			| MpmError::UnsafeArchive { .. }
+			| MpmError::Watch { .. } => 5,
+			MpmError::Dependency { source, .. } => source.exit_code(),
+			// the highest code among the failures wins
+			MpmError::Unsatisfied { failures, .. } => failures.iter().map(|failure| failure.exit_code()).max().unwrap_or(1),
+		}
+	}
+}
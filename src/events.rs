@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use semver::Version;
+use serde::Serialize;
+use super::output::WriteOutcome;
+
+static IS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// What `--message-format json` reports, one object per line on stdout with its kind under `reason`.
+/// Editor plugins read these, so the field names shouldn't change
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Message {
+	PackageDiscovered {
+		myth: String,
+		myth_path: PathBuf,
+		dependencies: usize,
+	},
+	/// The release a dependency resolved to, `version` is `None` for path dependencies
+	ResolutionChosen {
+		myth_path: PathBuf,
+		dependency: String,
+		source_url: String,
+		asset: Option<String>,
+		version: Option<Version>,
+	},
+	/// Sent by the first myth needing a release that isn't cached, others reuse the download
+	DownloadStarted {
+		myth_path: PathBuf,
+		dependency: String,
+		source_url: String,
+		asset: Option<String>,
+		version: Version,
+		/// `None` when the server doesn't say
+		total_bytes: Option<u64>,
+	},
+	DownloadFinished {
+		myth_path: PathBuf,
+		dependency: String,
+		source_url: String,
+		asset: Option<String>,
+		version: Version,
+		bytes: u64,
+	},
+	/// Sent for every generated file, whether or not it had to change
+	FileWritten {
+		/// `None` for `mpm build`
+		myth_path: Option<PathBuf>,
+		dependency: Option<String>,
+		output_path: PathBuf,
+		outcome: WriteOutcome,
+		/// The release it was compiled from, `None` for `mpm build` and path dependencies
+		version: Option<Version>,
+	},
+	Warning {
+		myth_path: Option<PathBuf>,
+		dependency: Option<String>,
+		message: String,
+	},
+	Error {
+		myth_path: Option<PathBuf>,
+		dependency: Option<String>,
+		message: String,
+		exit_code: i32,
+	},
+}
+
+/// Turns on `emit`, once set the binary leaves stdout to messages alone
+pub fn enable() {
+	IS_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+	return IS_ENABLED.load(Ordering::Relaxed);
+}
+
+/// Prints the message as a line of JSON, when enabled
+pub fn emit(message: Message) {
+	if !is_enabled() {
+		return;
+	}
+	println!("{}", serde_json::to_string(&message).expect("messages always serialize"));
+}
@@ -0,0 +1,303 @@
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use octocrab::Octocrab;
+use serde::Deserialize;
+use super::user_config::{UserConfig, HostConfig, DEFAULT_HOST, USER_DIR_NAME, get_user_dir_path};
+use super::error::{MpmError, Result};
+
+/// Checked in order, the first one set wins over the credentials file
+pub const TOKEN_ENV_VARS: [&str; 2] = ["MPM_GITHUB_TOKEN", "GITHUB_TOKEN"];
+pub const CREDENTIALS_FILE_NAME: &str = "credentials.toml";
+
+// a reset this close is cheaper to wait out than to fail the whole install over
+const MAX_RATE_LIMIT_WAIT_SECS: u64 = 90;
+
+#[derive(Debug, Deserialize)]
+struct RawCredentials {
+	pub github_token: Option<String>,
+}
+
+/// Where a repo lives and how to talk to the API serving it
+#[derive(Debug, Clone)]
+pub struct RepoLocation {
+	pub owner: String,
+	pub repo: String,
+	pub api_url: String,
+	pub token: Option<String>,
+}
+
+/// Path of the user level credentials file, `~/.mpm/credentials.toml`
+pub fn get_credentials_path() -> Option<PathBuf> {
+	return get_user_dir_path().map(|dir| dir.join(CREDENTIALS_FILE_NAME));
+}
+
+/// Finds a GitHub token from the environment, falling back to the credentials file
+pub fn find_token() -> Option<String> {
+	for var_name in TOKEN_ENV_VARS {
+		if let Ok(token) = env::var(var_name) {
+			if !token.trim().is_empty() {
+				return Some(token.trim().to_string());
+			}
+		}
+	}
+
+	let credentials_path: PathBuf = get_credentials_path()?;
+	let contents: String = fs::read_to_string(credentials_path).ok()?;
+	let credentials: RawCredentials = toml::from_str(&contents).ok()?;
+	return credentials.github_token.filter(|token| !token.trim().is_empty());
+}
+
+/// Splits a repo URL like `https://github.com/owner/repo` into its owner and repo using the host's configured web URL
+pub fn locate_repo(source_url: &str, user_config: &UserConfig) -> Result<RepoLocation> {
+	let invalid_url = |reason: String| MpmError::InvalidUrl {
+		url: source_url.to_string(),
+		reason,
+	};
+	let url: reqwest::Url = reqwest::Url::parse(source_url).map_err(|e| invalid_url(e.to_string()))?;
+	let host_name: &str = url.host_str().ok_or_else(|| invalid_url(String::from("it has no host")))?;
+	let host: String = match url.port() {
+		Some(port) => format!("{}:{}", host_name, port),
+		None => host_name.to_string(),
+	};
+
+	let host_config: HostConfig = user_config.get_host(&host).ok_or_else(|| MpmError::UnknownHost {
+		host: host.clone(),
+	})?;
+	let web_url: String = host_config.web_url.clone().unwrap_or_else(|| format!("https://{}", host));
+
+	let info_string: &str = source_url.strip_prefix(web_url.trim_end_matches('/'))
+		.ok_or_else(|| invalid_url(format!("it doesn't start with the web URL '{}' configured for '{}'", web_url, host)))?
+		.trim_matches('/');
+	let mid_slash_start: usize = info_string.find('/').ok_or_else(|| invalid_url(String::from("it's missing an owner or repo")))?;
+
+	let owner: &str = &info_string[..mid_slash_start];
+	let repo: &str = &info_string[(mid_slash_start+1)..];
+	if owner.is_empty() || repo.is_empty() || repo.contains('/') {
+		return Err(invalid_url(String::from("it isn't a '<web url>/<owner>/<repo>' URL")));
+	}
+
+	// the global token is for github.com, other hosts only ever get the one configured for them
+	let token: Option<String> = match host == DEFAULT_HOST {
+		true => host_config.token.or_else(find_token),
+		false => host_config.token,
+	};
+	return Ok(RepoLocation {
+		owner: owner.to_string(),
+		repo: repo.to_string(),
+		api_url: host_config.api_url,
+		token,
+	});
+}
+
+/// Flattens an octocrab error into one line, its own Display can carry a whole backtrace
+pub fn describe_octocrab_error(error: &octocrab::Error) -> String {
+	if let octocrab::Error::GitHub { source, .. } = error {
+		return format!("{} ({})", source.message, source.status_code);
+	}
+	let mut messages: Vec<String> = Vec::new();
+	let mut source_opt: Option<&dyn std::error::Error> = std::error::Error::source(error);
+	while let Some(source) = source_opt {
+		messages.push(source.to_string());
+		source_opt = source.source();
+	}
+	if messages.is_empty() {
+		messages.push(error.to_string().lines().next().unwrap_or_default().to_string());
+	}
+	return messages.join(": ");
+}
+
+/// Builds an octocrab client for the location's API, authenticated when a token is available
+pub fn build_client(location: &RepoLocation) -> Result<Octocrab> {
+	let to_error = |e: octocrab::Error| MpmError::GitHub {
+		source_url: location.api_url.clone(),
+		message: describe_octocrab_error(&e),
+	};
+	let mut builder = Octocrab::builder()
+		.base_uri(location.api_url.as_str())
+		.map_err(to_error)?;
+	if let Some(token) = &location.token {
+		builder = builder.personal_token(token.clone());
+	}
+	return builder.build().map_err(to_error);
+}
+
+/// Starts a GET request carrying the same credentials as the octocrab client
+pub fn download_request(url: &str, location: &RepoLocation) -> reqwest::RequestBuilder {
+	let client = reqwest::Client::new();
+	let mut request: reqwest::RequestBuilder = client.get(url)
+		.header("User-Agent", "mpm");
+	if let Some(token) = &location.token {
+		request = request.bearer_auth(token);
+	}
+	return request;
+}
+
+fn format_reset(reset: u64) -> String {
+	match Utc.timestamp_opt(reset as i64, 0).single() {
+		Some(reset_time) => {
+			let local_time: DateTime<Local> = reset_time.with_timezone(&Local);
+			local_time.format("%Y-%m-%d %H:%M:%S %Z").to_string()
+		},
+		None => format!("unix time {}", reset),
+	}
+}
+
+fn get_seconds_until(reset: u64) -> u64 {
+	let now: u64 = Utc::now().timestamp().max(0) as u64;
+	return reset.saturating_sub(now);
+}
+
+fn rate_limit_error(source_url: &str, reset_opt: Option<u64>, has_token: bool) -> MpmError {
+	let hint: String = if has_token {
+		String::new()
+	}else{
+		format!(" (set {} or add github_token to ~/{}/{} for a higher limit)", TOKEN_ENV_VARS[0], USER_DIR_NAME, CREDENTIALS_FILE_NAME)
+	};
+	return MpmError::RateLimited {
+		source_url: source_url.to_string(),
+		reset: reset_opt.map(format_reset),
+		hint,
+	};
+}
+
+fn is_rate_limited(error: &octocrab::Error) -> bool {
+	if let octocrab::Error::GitHub { source, .. } = error {
+		let status: u16 = source.status_code.as_u16();
+		return (status == 403 || status == 429) && source.message.to_lowercase().contains("rate limit");
+	}
+	return false;
+}
+
+async fn get_core_reset(client: &Octocrab) -> Option<u64> {
+	// the rate limit endpoint itself doesn't count against the limit
+	return client.ratelimit().get().await.ok().map(|rate_limit| rate_limit.resources.core.reset);
+}
+
+/// Sends a GitHub API request, waiting out a rate limit that resets soon and failing with the reset time otherwise
+pub async fn send_with_rate_limit<T, F, Fut>(client: &Octocrab, location: &RepoLocation, source_url: &str, request: F) -> Result<T>
+where
+	F: Fn() -> Fut,
+	Fut: Future<Output = octocrab::Result<T>>,
+{
+	let to_error = |e: octocrab::Error| MpmError::GitHub {
+		source_url: source_url.to_string(),
+		message: describe_octocrab_error(&e),
+	};
+	match request().await {
+		Ok(value) => return Ok(value),
+		Err(e) if is_rate_limited(&e) => {
+			let reset_opt: Option<u64> = get_core_reset(client).await;
+			match reset_opt {
+				Some(reset) if get_seconds_until(reset) <= MAX_RATE_LIMIT_WAIT_SECS => {
+					let wait_secs: u64 = get_seconds_until(reset) + 1;
+					tracing::warn!(source_url, wait_secs, "rate limited by GitHub, retrying in {}s", wait_secs);
+					tokio::time::sleep(Duration::from_secs(wait_secs)).await;
+					return request().await.map_err(|e| {
+						if is_rate_limited(&e) { rate_limit_error(source_url, Some(reset), location.token.is_some()) } else { to_error(e) }
+					});
+				},
+				_ => return Err(rate_limit_error(source_url, reset_opt, location.token.is_some())),
+			}
+		},
+		Err(e) => return Err(to_error(e)),
+	}
+}
+
+/// Turns a rate limited or otherwise failed download response into an error
+pub fn check_download_response(response: reqwest::Response, location: &RepoLocation, source_url: &str) -> Result<reqwest::Response> {
+	let status: reqwest::StatusCode = response.status();
+	if status.is_success() {
+		return Ok(response);
+	}
+
+	let remaining_opt: Option<&str> = response.headers().get("x-ratelimit-remaining").and_then(|v| v.to_str().ok());
+	if (status.as_u16() == 403 || status.as_u16() == 429) && remaining_opt == Some("0") {
+		let reset_opt: Option<u64> = response.headers().get("x-ratelimit-reset")
+			.and_then(|v| v.to_str().ok())
+			.and_then(|v| v.parse::<u64>().ok());
+		return Err(rate_limit_error(source_url, reset_opt, location.token.is_some()));
+	}
+
+	return Err(MpmError::Download {
+		url: response.url().to_string(),
+		message: format!("server responded with {}", status),
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+	use std::path::Path;
+	use crate::workspace::Workspace;
+	use crate::user_config::CONFIG_PATH_ENV_VAR;
+
+	const GLOBAL_TOKEN: &str = "global-token";
+
+	/// Every test sets the same values, so running them side by side can't change what another sees
+	fn set_global_token() {
+		env::set_var(TOKEN_ENV_VARS[0], GLOBAL_TOKEN);
+		env::set_var(CONFIG_PATH_ENV_VAR, "/nonexistent/mpm-config.toml");
+	}
+
+	fn host(api_url: &str, token: Option<&str>) -> HostConfig {
+		return HostConfig {
+			api_url: api_url.to_string(),
+			web_url: None,
+			token: token.map(|token| token.to_string()),
+		};
+	}
+
+	fn user_config(hosts: &[(&str, HostConfig)]) -> UserConfig {
+		return UserConfig {
+			hosts: hosts.iter().map(|(name, host_config)| (name.to_string(), host_config.clone())).collect::<HashMap<String, HostConfig>>(),
+			..UserConfig::default()
+		};
+	}
+
+	fn write_workspace(dir_path: &Path, contents: &str) -> Result<Workspace> {
+		fs::write(dir_path.join("mpm.toml"), contents).expect("the workspace config is written");
+		return Workspace::find(dir_path);
+	}
+
+	#[test]
+	fn github_gets_the_global_token() {
+		set_global_token();
+		let location: RepoLocation = locate_repo("https://github.com/owner/repo", &user_config(&[])).expect("the repo is located");
+		assert_eq!(location.token.as_deref(), Some(GLOBAL_TOKEN));
+		assert_eq!(location.api_url, "https://api.github.com");
+
+		// a token configured for github.com itself wins over the global one
+		let config: UserConfig = user_config(&[(DEFAULT_HOST, host("https://api.github.com", Some("own-token")))]);
+		let location: RepoLocation = locate_repo("https://github.com/owner/repo", &config).expect("the repo is located");
+		assert_eq!(location.token.as_deref(), Some("own-token"));
+	}
+
+	#[test]
+	fn other_hosts_never_get_the_global_token() {
+		set_global_token();
+		let config: UserConfig = user_config(&[
+			("ghe.example.com", host("https://ghe.example.com/api/v3", None)),
+			("other.example.com", host("https://other.example.com/api/v3", Some("other-token"))),
+		]);
+		let location: RepoLocation = locate_repo("https://ghe.example.com/owner/repo", &config).expect("the repo is located");
+		assert_eq!(location.token, None);
+		let location: RepoLocation = locate_repo("https://other.example.com/owner/repo", &config).expect("the repo is located");
+		assert_eq!(location.token.as_deref(), Some("other-token"));
+	}
+
+	#[test]
+	fn workspace_hosts_only_get_their_own_token() {
+		set_global_token();
+		let temp_dir: tempfile::TempDir = tempfile::tempdir().expect("the temp dir is created");
+		let workspace: Workspace = write_workspace(temp_dir.path(), "[hosts.\"evil.example.com\"]\napi_url = \"https://evil.example.com/api\"\n").expect("the workspace loads");
+		let config: UserConfig = UserConfig::load(&workspace).expect("the user config loads");
+		let location: RepoLocation = locate_repo("https://evil.example.com/owner/repo", &config).expect("the repo is located");
+		assert_eq!(location.api_url, "https://evil.example.com/api");
+		assert_eq!(location.token, None);
+	}
+}
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+use serde::Serialize;
+use super::package::MPMPackage;
+use super::package_source::{PackageSource, PackageSourceContent, SourceCache, SourceType, get_cache_key, get_psc_from_cache, read_package_manifest};
+use super::manifest::RawMPMConfig;
+use super::error::Result;
+
+/// A dependency as the cache would resolve it, along with what its own manifest depends on
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyNode {
+	pub name: String,
+	pub source_url: String,
+	pub asset: Option<String>,
+	pub inner_path: String,
+	pub version_req: String,
+	/// Newest cached release matching `version_req`, `None` when nothing matching is cached yet
+	pub resolved_version: Option<String>,
+	/// Path dependencies are compiled from disk, so they have no version
+	pub is_local: bool,
+	/// Set when this package already appeared further up the same branch, its children aren't repeated
+	pub is_cycle: bool,
+	pub children: Vec<DependencyNode>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MythNode {
+	pub name: String,
+	pub manifest_path: PathBuf,
+	pub dependencies: Vec<DependencyNode>,
+}
+
+/// One way a myth ends up depending on a package, from the myth's own dependency down to the package
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyPath {
+	pub myth: String,
+	pub chain: Vec<DependencyNode>,
+}
+
+impl DependencyNode {
+	/// Where the package comes from, as it'd be written after `/releases/tag/<version>`
+	pub fn describe_source(self: &Self) -> String {
+		let mut description: String = self.source_url.clone();
+		if !self.inner_path.is_empty() {
+			description = format!("{}/{}", description, self.inner_path);
+		}
+		if let Some(asset_name) = &self.asset {
+			description = format!("{} asset '{}'", description, asset_name);
+		}
+		return description;
+	}
+
+	fn describe_version(self: &Self) -> String {
+		if self.is_local {
+			return String::from("local");
+		}
+		match &self.resolved_version {
+			Some(version) => format!("{} -> {}", self.version_req, version),
+			None => format!("{} -> not cached", self.version_req),
+		}
+	}
+
+	/// Whether `target` names this package, either by dependency name or by (the end of) its source
+	pub fn matches(self: &Self, target: &str) -> bool {
+		if self.name.eq_ignore_ascii_case(target) {
+			return true;
+		}
+		let target: &str = target.trim_matches('/');
+		let full_source: String = format!("{}/{}", self.source_url, self.inner_path);
+		for source in [self.source_url.as_str(), full_source.trim_end_matches('/')] {
+			if source == target || source.ends_with(&format!("/{}", target)) {
+				return true;
+			}
+		}
+		return false;
+	}
+}
+
+fn build_node(
+	name: String,
+	source: &PackageSource,
+	source_cache: &mut SourceCache,
+	ancestors: &mut Vec<(PathBuf, String)>,
+) -> Result<DependencyNode> {
+	let is_local: bool = source.source_type == SourceType::Local;
+	let cache_key: PathBuf = get_cache_key(&source.source_url, &source.asset);
+	let content_opt: Option<PackageSourceContent> = match is_local {
+		true => None,
+		false => get_psc_from_cache(cache_key.clone(), source.version_req.clone(), source_cache),
+	};
+
+	let ancestor: (PathBuf, String) = (cache_key, source.inner_path.clone());
+	let is_cycle: bool = ancestors.contains(&ancestor);
+	let mut children: Vec<DependencyNode> = Vec::new();
+	if !is_cycle {
+		let raw_config_opt: Option<RawMPMConfig> = match &content_opt {
+			Some(content) => source_cache.read_manifest(content, &source.inner_path)?,
+			None if is_local => read_package_manifest(&source.source_url)?,
+			None => None,
+		};
+		if let Some(raw_config) = raw_config_opt {
+			ancestors.push(ancestor);
+			children = build_children(raw_config, source_cache, ancestors)?;
+			ancestors.pop();
+		}
+	}
+
+	return Ok(DependencyNode {
+		name,
+		source_url: source.source_url.to_string_lossy().to_string(),
+		asset: source.asset.clone(),
+		// a path dependency's source is already the whole path
+		inner_path: if is_local { String::new() } else { source.inner_path.clone() },
+		version_req: source.version_req.to_string(),
+		resolved_version: content_opt.map(|content| content.version.to_string()),
+		is_local,
+		is_cycle,
+		children,
+	});
+}
+
+fn build_children(raw_config: RawMPMConfig, source_cache: &mut SourceCache, ancestors: &mut Vec<(PathBuf, String)>) -> Result<Vec<DependencyNode>> {
+	let mut children: Vec<DependencyNode> = Vec::new();
+	for raw_dependency in raw_config.dependencies {
+		// path dependencies point into whoever published the package's disk, there's nothing to follow
+		let url: String = match &raw_dependency.url {
+			Some(url) => url.clone(),
+			None => continue,
+		};
+		// a broken manifest in someone else's package isn't ours to fix, so those entries are left out
+		let source: PackageSource = match PackageSource::new(url, raw_dependency.asset.clone()) {
+			Ok(source) => source,
+			Err(e) => {
+				tracing::warn!(dependency = raw_dependency.name, "skipping '{}': {}", raw_dependency.name, e);
+				continue;
+			},
+		};
+		children.push(build_node(raw_dependency.name, &source, source_cache, ancestors)?);
+	}
+	return Ok(children);
+}
+
+/// Builds the dependency graph of every myth from the cache alone, nothing is downloaded
+pub fn build_tree(mpm_packages: &[MPMPackage], source_cache: &mut SourceCache) -> Result<Vec<MythNode>> {
+	let mut myth_nodes: Vec<MythNode> = Vec::new();
+	for mpm_package in mpm_packages {
+		let mut dependencies: Vec<DependencyNode> = Vec::new();
+		for mpm_dependency in &mpm_package.dependencies {
+			dependencies.push(build_node(mpm_dependency.name.clone(), &mpm_dependency.source, source_cache, &mut Vec::new())?);
+		}
+		myth_nodes.push(MythNode {
+			name: mpm_package.name.clone(),
+			manifest_path: mpm_package.config_path_buf.clone(),
+			dependencies,
+		});
+	}
+	return Ok(myth_nodes);
+}
+
+fn collect_paths(myth: &str, node: &DependencyNode, target: &str, chain: &mut Vec<DependencyNode>, paths: &mut Vec<DependencyPath>) {
+	let mut step: DependencyNode = node.clone();
+	step.children = Vec::new();
+	chain.push(step);
+	if node.matches(target) {
+		paths.push(DependencyPath {
+			myth: myth.to_string(),
+			chain: chain.clone(),
+		});
+	}
+	for child in &node.children {
+		collect_paths(myth, child, target, chain, paths);
+	}
+	chain.pop();
+}
+
+/// Every path through the graph that ends at a package matching `target`
+pub fn find_paths(myth_nodes: &[MythNode], target: &str) -> Vec<DependencyPath> {
+	let mut paths: Vec<DependencyPath> = Vec::new();
+	for myth_node in myth_nodes {
+		for node in &myth_node.dependencies {
+			collect_paths(&myth_node.name, node, target, &mut Vec::new(), &mut paths);
+		}
+	}
+	return paths;
+}
+
+fn render_nodes(nodes: &[DependencyNode], prefix: &str, output: &mut String) {
+	for (i, node) in nodes.iter().enumerate() {
+		let is_last: bool = i + 1 == nodes.len();
+		let cycle_note: &str = if node.is_cycle { " (cycle)" } else { "" };
+		output.push_str(&format!(
+			"{}{} {} {} ({}){}\n",
+			prefix,
+			if is_last { "└──" } else { "├──" },
+			node.name,
+			node.describe_version(),
+			node.describe_source(),
+			cycle_note
+		));
+		let child_prefix: String = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+		render_nodes(&node.children, &child_prefix, output);
+	}
+}
+
+/// Draws the graph the way `cargo tree` does
+pub fn render_tree(myth_nodes: &[MythNode]) -> String {
+	let mut output: String = String::new();
+	for myth_node in myth_nodes {
+		output.push_str(&format!("{} ({})\n", myth_node.name, myth_node.manifest_path.display()));
+		render_nodes(&myth_node.dependencies, "", &mut output);
+	}
+	return output;
+}
+
+/// One line per path, from the myth down to the package
+pub fn render_paths(paths: &[DependencyPath]) -> String {
+	let mut output: String = String::new();
+	for path in paths {
+		let steps: Vec<String> = path.chain.iter()
+			.map(|node| match (node.is_local, &node.resolved_version) {
+				// path dependencies are never cached, there's no version to show
+				(true, _) => format!("{} local", node.name),
+				(false, Some(version)) => format!("{} {}", node.name, version),
+				(false, None) => format!("{} (not cached)", node.name),
+			})
+			.collect();
+		output.push_str(&format!("{} > {}", path.myth, steps.join(" > ")));
+		if let Some(last) = path.chain.last() {
+			output.push_str(&format!(" ({})", last.describe_source()));
+		}
+		output.push('\n');
+	}
+	return output;
+}
@@ -1,3 +1,18 @@
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod package;
 pub mod package_source;
-pub mod csharp_parse;
\ No newline at end of file
+pub mod csharp_parse;
+pub mod github;
+pub mod user_config;
+pub mod workspace;
+pub mod error;
+pub mod cache;
+pub mod diagnostic;
+pub mod manifest;
+pub mod graph;
+pub mod outdated;
+pub mod output;
+pub mod watch;
+pub mod collect;
+pub mod logging;
+pub mod events;
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing::{Event, Level, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::{format, FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+static IS_JSON: AtomicBool = AtomicBool::new(false);
+static IS_PROGRESS_SHOWN: AtomicBool = AtomicBool::new(true);
+static IS_TIMESTAMPED: AtomicBool = AtomicBool::new(false);
+
+/// Collects just the message of an event, the other fields are only shown as JSON
+#[derive(Default)]
+struct MessageVisitor {
+	message: String,
+}
+
+impl Visit for MessageVisitor {
+	fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+		if field.name() == "message" {
+			self.message = format!("{:?}", value);
+		}
+	}
+}
+
+/// Prints events the way mpm always has, with a prefix like `warning: ` for anything that isn't plain progress,
+/// after the time when `show_timestamps` was called
+struct TextFormat;
+
+impl<S, N> FormatEvent<S, N> for TextFormat
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+	N: for<'a> FormatFields<'a> + 'static,
+{
+	fn format_event(&self, _ctx: &FmtContext<'_, S, N>, mut writer: format::Writer<'_>, event: &Event<'_>) -> fmt::Result {
+		let prefix: &str = match *event.metadata().level() {
+			Level::ERROR => "error: ",
+			Level::WARN => "warning: ",
+			Level::INFO => "",
+			Level::DEBUG => "debug: ",
+			Level::TRACE => "trace: ",
+		};
+		let mut visitor: MessageVisitor = MessageVisitor::default();
+		event.record(&mut visitor);
+		if IS_TIMESTAMPED.load(Ordering::Relaxed) {
+			write!(writer, "[{}] ", chrono::Local::now().format("%H:%M:%S"))?;
+		}
+		return writeln!(writer, "{}{}", prefix, visitor.message);
+	}
+}
+
+/// Sends every log event to stderr, as text or as one JSON object per line. Progress bars are only drawn for text
+/// at the default verbosity or above, since they'd be noise in a machine-read stream
+pub fn init(max_level: LevelFilter, is_json: bool) {
+	IS_JSON.store(is_json, Ordering::Relaxed);
+	IS_PROGRESS_SHOWN.store(!is_json && max_level >= LevelFilter::INFO, Ordering::Relaxed);
+	// the http and GitHub crates underneath are only worth hearing from at the most verbose
+	let dependency_level: LevelFilter = match max_level >= LevelFilter::TRACE {
+		true => LevelFilter::DEBUG,
+		false => LevelFilter::WARN.min(max_level),
+	};
+	let targets: Targets = Targets::new()
+		.with_target("libmuse", max_level)
+		.with_target("mpm", max_level)
+		.with_default(dependency_level);
+	let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+	// the only way this fails is being called twice, which leaves the first setup in place
+	let _ = match is_json {
+		true => tracing_subscriber::registry()
+			.with(layer.json().flatten_event(true).with_current_span(false).with_filter(targets))
+			.try_init(),
+		false => tracing_subscriber::registry()
+			.with(layer.event_format(TextFormat).with_filter(targets))
+			.try_init(),
+	};
+}
+
+/// Starts each text log line with the time, for `--watch` where runs pile up. JSON lines always have their timestamp
+pub fn show_timestamps() {
+	IS_TIMESTAMPED.store(true, Ordering::Relaxed);
+}
+
+/// Whether logs are going out as JSON, so errors can be reported the same way
+pub fn is_json() -> bool {
+	return IS_JSON.load(Ordering::Relaxed);
+}
+
+/// A bar counting the bytes of a download, hidden when progress isn't shown or stderr isn't a terminal
+pub fn download_bar(total_bytes: Option<u64>, label: &str) -> ProgressBar {
+	let progress_bar: ProgressBar = match total_bytes {
+		Some(total_bytes) => ProgressBar::new(total_bytes).with_style(
+			ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})")
+				.expect("the template is valid")
+				.progress_chars("=> ")
+		),
+		// GitHub doesn't always say how big a zipball is
+		None => ProgressBar::new_spinner().with_style(
+			ProgressStyle::with_template("{spinner} {msg} {bytes} ({bytes_per_sec})")
+				.expect("the template is valid")
+		),
+	};
+	let draw_target: ProgressDrawTarget = match IS_PROGRESS_SHOWN.load(Ordering::Relaxed) {
+		true => ProgressDrawTarget::stderr(),
+		false => ProgressDrawTarget::hidden(),
+	};
+	progress_bar.set_draw_target(draw_target);
+	progress_bar.set_message(label.to_string());
+	return progress_bar;
+}
@@ -1,29 +1,141 @@
+#![allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 extern crate regex;
 extern crate rand;
 
 use std::fs;
-use std::io::Read;
-use std::str::FromStr;
-use clap::{Parser, Subcommand};
-use libmuse::package::{search_for_packages, MPMPackage, find_package, FILE_NAME_STRING};
-use libmuse::package_source::PackageSourceContent;
-use libmuse::csharp_parse::compile_to_single_script;
+use clap::{Parser, Subcommand, ValueEnum};
+use clap::builder::FalseyValueParser;
+use libmuse::package::{search_for_packages, select_packages, find_myth_manifest_paths, MPMPackage, Drift, Resolution, SolveOptions, find_package, find_manifest_path, FILE_NAME_STRING};
+use libmuse::package_source::{SourceCache, SourceType, ScriptSource, find_release, parse_source_header};
+use libmuse::collect::{collect_scripts, ScriptFilter};
+use libmuse::watch::{ChangeWatcher, WatchTarget};
+use libmuse::manifest::{add_dependency, remove_dependency, render_new_manifest, RawMPMConfig};
+use libmuse::diagnostic::Diagnostic;
+use libmuse::user_config::{UserConfig, DEFAULT_WEB_URL};
+use libmuse::outdated::{check_outdated, render_table, OutdatedEntry};
+use libmuse::graph::{build_tree, find_paths, render_tree, render_paths, MythNode, DependencyPath};
+use semver::{Version, VersionReq};
+use libmuse::cache::{load_source_cache, save_source_cache};
+use libmuse::csharp_parse::{compile_to_single_script, CompileOptions};
+use libmuse::workspace::{Workspace, WORKSPACE_FILE_NAME};
+use libmuse::output::{write_if_changed, summarize, WrittenFile};
+use libmuse::error::{MpmError, Result};
+use libmuse::logging;
+use libmuse::events::{self, Message};
+use tracing::level_filters::LevelFilter;
 use std::{collections::HashMap, env, path::PathBuf, path::Path};
-use base64::{encode_config, decode_config, URL_SAFE_NO_PAD};
-use semver::Version;
 #[derive(Parser)]
 #[command(name = "mpm", about = "A Rust-based package manager for Project Frontier", long_about = None)]
 struct Args {
 	#[command(subcommand)]
 	command: MPMCommand,
+	/// Log more, `-vv` for everything
+	#[arg(short, long, global = true, action = clap::ArgAction::Count)]
+	verbose: u8,
+	/// Only log warnings and errors
+	#[arg(short, long, global = true, conflicts_with = "verbose")]
+	quiet: bool,
+	/// Log as text, or as one JSON object per line on stderr
+	#[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+	log_format: OutputFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+	Text,
+	Json,
 }
 
 #[derive(Subcommand)]
 enum MPMCommand {
 	// uses a directory with a muse-package.toml to load in packages
 	Install {
+		/// Myth to install, as its directory or its name. Repeatable, every myth under the current directory by default
 		#[arg(short = 'c', long)]
-		myth: Option<PathBuf>,
+		myth: Vec<String>,
+		/// Resolve strictly from the local cache, never touching the network
+		#[arg(long, env = "MPM_OFFLINE", value_parser = FalseyValueParser::new())]
+		offline: bool,
+		/// Fail instead of warning when a dependency is deprecated, meant for CI
+		#[arg(long, env = "MPM_DENY_DEPRECATED", value_parser = FalseyValueParser::new())]
+		deny_deprecated: bool,
+		/// Only check that the generated scripts match what the cache compiles to, same as `mpm check`
+		#[arg(long, conflicts_with = "watch")]
+		frozen: bool,
+		/// Keep running, reinstalling whenever a manifest or path dependency changes
+		#[arg(long)]
+		watch: bool,
+		/// `json` prints one message per line on stdout for each step, for editors and scripts to follow
+		#[arg(long, value_enum, default_value = "text", conflicts_with = "frozen")]
+		message_format: OutputFormat,
+	},
+	/// Recompiles every dependency from the cache and diffs it against its script, without writing anything
+	Check {
+		/// Myth to check, as its directory or its name. Repeatable, every myth under the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Vec<String>,
+	},
+	/// Prints each myth's dependencies and the versions the cache resolves them to
+	Tree {
+		/// Myth to show, as its directory or its name. Repeatable, every myth under the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Vec<String>,
+		#[arg(long, value_enum, default_value = "text")]
+		format: OutputFormat,
+	},
+	/// Lists every path from a myth to a package, given by dependency name or source like `owner/repo`
+	Why {
+		target: String,
+		/// Myth to look in, as its directory or its name. Repeatable, every myth under the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Vec<String>,
+		#[arg(long, value_enum, default_value = "text")]
+		format: OutputFormat,
+	},
+	/// Compares every dependency with the newest compatible and newest overall release upstream
+	Outdated {
+		/// Myth to compare, as its directory or its name. Repeatable, every myth under the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Vec<String>,
+		#[arg(long, value_enum, default_value = "text")]
+		format: OutputFormat,
+		/// Exit with 1 when any dependency is behind the latest release
+		#[arg(long)]
+		exit_code: bool,
+	},
+	/// Creates a commented muse-package.toml for a myth
+	Init {
+		/// Myth directory, the current directory by default
+		dir: Option<PathBuf>,
+		/// Fill in dependencies from the headers of scripts mpm already compiled there
+		#[arg(long)]
+		detect: bool,
+	},
+	/// Adds a dependency on the newest matching release to a myth's muse-package.toml and installs it
+	Add {
+		/// Name of the dependency, used for its script and namespace
+		name: String,
+		/// Repository as `owner/repo` on github.com, or its full URL on another host
+		repo: String,
+		/// Path inside the release to the package's scripts
+		#[arg(long, required_unless_present = "asset")]
+		path: Option<String>,
+		/// Version requirement the release has to match, like `^0.2`, any version by default
+		#[arg(long)]
+		version: Option<String>,
+		/// Name of a release asset to download instead of the zipball
+		#[arg(long)]
+		asset: Option<String>,
+		/// Myth to add it to, as its directory or its name, the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Option<String>,
+	},
+	/// Removes a dependency from a myth's muse-package.toml along with its generated script
+	Remove {
+		name: String,
+		/// Myth to remove it from, as its directory or its name, the current directory by default
+		#[arg(short = 'c', long)]
+		myth: Option<String>,
 	},
 	/// Takes a directory of .cs files and combines them into a single one
 	Build {
@@ -31,137 +143,583 @@ enum MPMCommand {
 		input: PathBuf,
 		#[arg(short, long)]
 		output: PathBuf,
+		/// Only build files matching this glob, relative to the input directory. Repeatable, replaces the input's manifest `include`
+		#[arg(long)]
+		include: Vec<String>,
+		/// Leave out files matching this glob. Repeatable, replaces the input's manifest `exclude`
+		#[arg(long)]
+		exclude: Vec<String>,
+		/// Keep running, rebuilding whenever a file in the input directory changes
+		#[arg(long)]
+		watch: bool,
+		/// `json` prints one message per line on stdout for each step, for editors and scripts to follow
+		#[arg(long, value_enum, default_value = "text")]
+		message_format: OutputFormat,
 	},
 }
 
-const CACHE_DIR_NAME: &str = ".mpm-cache";
+/// Errors as log events for `--log-format json`, so the stream stays parseable
+fn log_error(error: &MpmError) {
+	match error {
+		MpmError::Unsatisfied { myth, failures } => {
+			for failure in failures {
+				tracing::error!(myth, exit_code = failure.exit_code(), "{}", failure);
+			}
+		},
+		MpmError::Manifest { diagnostics } => {
+			for diagnostic in diagnostics {
+				tracing::error!(
+					path = %diagnostic.path.display(),
+					line = diagnostic.location.as_ref().map(|location| location.line),
+					column = diagnostic.location.as_ref().map(|location| location.column),
+					key = diagnostic.key,
+					help = diagnostic.help,
+					exit_code = error.exit_code(),
+					"{}", diagnostic.message
+				);
+			}
+		},
+		_ => tracing::error!(exit_code = error.exit_code(), "{}", error),
+	}
+}
 
-fn encode_str_to_path_safe_b64(value: String) -> String {
-	encode_config(value, URL_SAFE_NO_PAD)
+/// Errors as `--message-format json` messages. A myth's failed dependencies were already sent as they failed
+fn emit_error(error: &MpmError) {
+	match error {
+		MpmError::Unsatisfied { .. } => {},
+		MpmError::Manifest { diagnostics } => {
+			for diagnostic in diagnostics {
+				events::emit(Message::Error {
+					myth_path: Some(diagnostic.path.clone()),
+					dependency: None,
+					message: diagnostic.message.clone(),
+					exit_code: error.exit_code(),
+				});
+			}
+		},
+		_ => events::emit(Message::Error {
+			myth_path: None,
+			dependency: None,
+			message: error.to_string(),
+			exit_code: error.exit_code(),
+		}),
+	}
 }
 
-fn decode_path_safe_b64_to_str(encoded: &str) -> String {
-	String::from_utf8(decode_config(encoded, URL_SAFE_NO_PAD).unwrap()).unwrap()
+fn report_error(error: &MpmError) {
+	emit_error(error);
+	if logging::is_json() {
+		return log_error(error);
+	}
+	// through the log, so `--watch` gets the time in front
+	match error {
+		MpmError::Unsatisfied { myth, failures } => {
+			let lines: Vec<String> = failures.iter().map(|failure| format!("  - {}", failure)).collect();
+			tracing::error!("{} dependencies of '{}' couldn't be satisfied:\n{}", failures.len(), myth, lines.join("\n"));
+		},
+		MpmError::Manifest { diagnostics } => {
+			for diagnostic in diagnostics {
+				eprintln!("{}\n", diagnostic);
+			}
+			tracing::error!("couldn't load manifests, {}", error);
+		},
+		_ => tracing::error!("{}", error),
+	}
 }
 
-#[tokio::main]
-async fn main() {
-	let args: Args = Args::parse();
+/// The myths given with `--myth`, or every myth under the current directory
+fn find_myths(myths: &[String], cwd_path: &Path, workspace: &Workspace) -> Result<Vec<MPMPackage>> {
+	match myths.is_empty() {
+		true => return search_for_packages(cwd_path, workspace),
+		false => return select_packages(myths, cwd_path, workspace),
+	}
+}
+
+/// The directory of the single myth given with `--myth`, or the current directory
+fn find_myth_dir(myth: Option<String>, cwd_path: &Path, workspace: &Workspace) -> Result<PathBuf> {
+	let myth: String = match myth {
+		Some(myth) => myth,
+		None => return Ok(cwd_path.to_path_buf()),
+	};
+	let mut manifest_paths: Vec<PathBuf> = find_myth_manifest_paths(&myth, cwd_path, workspace)?;
+	if manifest_paths.len() > 1 {
+		return Err(MpmError::AmbiguousMyth {
+			name: myth,
+			paths: manifest_paths,
+		});
+	}
+	let manifest_path: PathBuf = manifest_paths.remove(0);
+	return Ok(manifest_path.parent().map(|p| p.to_path_buf()).unwrap_or_default());
+}
+
+/// Prints a line meant for people, which `--message-format json` leaves out to keep stdout to its messages
+fn print_status(line: &str) {
+	if !events::is_enabled() {
+		println!("{}", line);
+	}
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+	return serde_json::to_string_pretty(value).expect("graph types always serialize");
+}
+
+/// Prints every drifted script as a diff, exiting with 1 if any drifted, or the worst failure's code if any couldn't be compiled
+async fn check_myths(mpm_packages: Vec<MPMPackage>, workspace: &Workspace) -> Result<()> {
+	let mut source_cache: SourceCache = load_source_cache(&workspace.get_cache_path(), UserConfig::load(workspace)?)?;
+	// checking mustn't write anything, not even to the cache
+	source_cache.is_read_only = true;
+	let mut drifts: Vec<Drift> = Vec::new();
+	let mut failures: Vec<MpmError> = Vec::new();
+	for mpm_package in mpm_packages {
+		let mut resolution: Resolution<Drift> = mpm_package.check(&mut source_cache).await;
+		drifts.append(&mut resolution.resolved);
+		failures.extend(resolution.into_error());
+	}
+
+	for drift in &drifts {
+		print!("{}", drift.diff);
+	}
+	let mut exit_code: i32 = 0;
+	for failure in &failures {
+		report_error(failure);
+		exit_code = exit_code.max(failure.exit_code());
+	}
+	if !drifts.is_empty() {
+		tracing::error!(count = drifts.len(), "{} generated script{} out of date, run `mpm install` to regenerate", drifts.len(), if drifts.len() == 1 { " is" } else { "s are" });
+		exit_code = exit_code.max(1);
+	}
+	if exit_code != 0 {
+		std::process::exit(exit_code);
+	}
+	return Ok(());
+}
+
+/// Solves every myth, reporting failures as it goes. Returns the exit code of the worst failure, 0 when all went fine
+async fn install(mpm_packages: Vec<MPMPackage>, workspace: &Workspace, options: SolveOptions) -> Result<i32> {
+	let cache_path: &Path = &workspace.get_cache_path();
+	let mut source_cache: SourceCache = load_source_cache(cache_path, UserConfig::load(workspace)?)?;
+
+	let mut written_files: Vec<WrittenFile> = Vec::new();
+	let mut failures: Vec<MpmError> = Vec::new();
+	for mpm_package in mpm_packages {
+		events::emit(Message::PackageDiscovered {
+			myth: mpm_package.name.clone(),
+			myth_path: mpm_package.config_path_buf.clone(),
+			dependencies: mpm_package.dependencies.len(),
+		});
+		let mut resolution: Resolution<WrittenFile> = mpm_package.solve(&mut source_cache, options).await;
+		written_files.append(&mut resolution.resolved);
+		failures.extend(resolution.into_error());
+	}
+	save_source_cache(cache_path, &source_cache)?;
+	print_status(&summarize(&written_files));
+
+	// report every myth before bailing, the exit code comes from the worst of them
+	let mut exit_code: i32 = 0;
+	for failure in &failures {
+		report_error(failure);
+		exit_code = exit_code.max(failure.exit_code());
+	}
+	if exit_code != 0 && options.offline {
+		tracing::info!("note: running offline, only {} was searched", cache_path.display());
+	}
+	return Ok(exit_code);
+}
 
+/// The globs picking which files under `input` get built, from its manifest when it has one unless given on the command line,
+/// along with the workspace's compiler options as overridden by that manifest
+fn get_build_options(input: &Path, include: Vec<String>, exclude: Vec<String>, workspace: &Workspace) -> Result<(ScriptFilter, CompileOptions)> {
+	let manifest_path: PathBuf = input.join(FILE_NAME_STRING);
+	if !manifest_path.is_file() {
+		return Ok((ScriptFilter::default().overridden_by(include, exclude), workspace.config.compiler.clone()));
+	}
+	let contents: String = fs::read_to_string(&manifest_path).map_err(|e| MpmError::io("read", &manifest_path, e))?;
+	// unlike a downloaded package's, problems in this manifest are the user's to fix
+	let (raw_config, diagnostics): (RawMPMConfig, Vec<Diagnostic>) = RawMPMConfig::parse(&manifest_path, &contents);
+	if !diagnostics.is_empty() {
+		return Err(MpmError::Manifest {
+			diagnostics,
+		});
+	}
+	return Ok((raw_config.filter.overridden_by(include, exclude), workspace.config.compiler.clone().overridden_by(raw_config.compiler)));
+}
+
+/// Combines the files under `input` that `filter` selects into the single script `output`
+fn build(input: &Path, output: &Path, filter: &ScriptFilter, compile_options: &CompileOptions) -> Result<WrittenFile> {
+	// let source_namespace_name: String = input.file_stem().unwrap().to_str().unwrap().to_string();
+	let target_namespace_name: String = output.file_stem()
+		.and_then(|stem| stem.to_str())
+		.ok_or_else(|| MpmError::io("name", output, std::io::Error::new(std::io::ErrorKind::InvalidInput, "output has no file name")))?
+		.to_string();
+
+	tracing::info!(input = %input.display(), output = %output.display(), "building from {} to {}", input.display(), output.display());
+	// the output may sit among its inputs
+	let scripts: HashMap<String, String> = collect_scripts(input, filter, &[output.to_path_buf()])?;
+	let content: String = compile_to_single_script(
+		String::from("DO NOT EDIT!\n// Compiled using 'github.com/nightcycle/muse-package-manager'"),
+		target_namespace_name,
+		scripts,
+		compile_options
+	);
+	let written_file: WrittenFile = write_if_changed(output, &content)?;
+	events::emit(Message::FileWritten {
+		myth_path: None,
+		dependency: None,
+		output_path: written_file.path_buf.clone(),
+		outcome: written_file.outcome,
+		version: None,
+	});
+	return Ok(written_file);
+}
+
+fn describe_changes(changed_paths: &[PathBuf]) -> String {
+	let names: Vec<String> = changed_paths.iter()
+		.map(|path| path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(path.display().to_string()))
+		.collect();
+	return names.join(", ");
+}
+
+async fn watch_build(input: &Path, output: &Path, filter: &ScriptFilter, compile_options: &CompileOptions) -> Result<()> {
+	let mut watcher: ChangeWatcher = ChangeWatcher::new()?;
+	let target: WatchTarget = WatchTarget {
+		dir_path: input.to_path_buf(),
+		recursive: true,
+		file_names: Vec::new(),
+	};
+	watcher.set_targets(vec![target], vec![output.to_path_buf()])?;
+	logging::show_timestamps();
+	loop {
+		match build(input, output, filter, compile_options) {
+			Ok(written_file) => tracing::info!("{}", summarize(&[written_file])),
+			Err(e) => report_error(&e),
+		}
+		tracing::info!("watching {} for changes", input.display());
+		let changed_paths: Vec<PathBuf> = tokio::task::block_in_place(|| watcher.wait_for_change())?;
+		tracing::info!("{} changed, rebuilding", describe_changes(&changed_paths));
+	}
+}
+
+/// Manifests, and the directories of path dependencies, that a reinstall depends on
+fn get_install_targets(myths: &[String], cwd: &Path, workspace: &Workspace, mpm_packages: &[MPMPackage]) -> (Vec<WatchTarget>, Vec<PathBuf>) {
+	let mut targets: Vec<WatchTarget> = vec![WatchTarget {
+		dir_path: workspace.root_path.clone(),
+		recursive: false,
+		file_names: vec![WORKSPACE_FILE_NAME.to_string()],
+	}];
+	match myths.is_empty() || mpm_packages.is_empty() {
+		// without --myth, or when the myths couldn't be read, a manifest anywhere below may be added or fixed
+		true => targets.push(WatchTarget {
+			dir_path: cwd.to_path_buf(),
+			recursive: true,
+			file_names: vec![FILE_NAME_STRING.to_string()],
+		}),
+		false => {
+			for mpm_package in mpm_packages {
+				targets.push(WatchTarget {
+					dir_path: mpm_package.config_path_buf.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+					recursive: false,
+					file_names: vec![FILE_NAME_STRING.to_string()],
+				});
+			}
+		},
+	}
+	let mut generated_paths: Vec<PathBuf> = Vec::new();
+	for mpm_package in mpm_packages {
+		for mpm_dependency in &mpm_package.dependencies {
+			generated_paths.push(mpm_dependency.path_buf.clone());
+			if mpm_dependency.source.source_type != SourceType::Local {
+				continue;
+			}
+			let package_path: &Path = &mpm_dependency.source.source_url;
+			match package_path.is_file() {
+				true => targets.push(WatchTarget {
+					dir_path: package_path.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+					recursive: false,
+					file_names: package_path.file_name().map(|n| vec![n.to_string_lossy().to_string()]).unwrap_or_default(),
+				}),
+				false => targets.push(WatchTarget {
+					dir_path: package_path.to_path_buf(),
+					recursive: true,
+					file_names: Vec::new(),
+				}),
+			}
+		}
+	}
+	return (targets, generated_paths);
+}
+
+async fn watch_install(myths: Vec<String>, cwd: &Path, options: SolveOptions) -> Result<()> {
+	let mut watcher: ChangeWatcher = ChangeWatcher::new()?;
+	logging::show_timestamps();
+	loop {
+		// manifests and the workspace config are read again every time, they may be what changed
+		let workspace: Workspace = Workspace::find(cwd)?;
+		let mpm_packages: Vec<MPMPackage> = match find_myths(&myths, cwd, &workspace) {
+			Ok(mpm_packages) => mpm_packages,
+			Err(e) => {
+				report_error(&e);
+				Vec::new()
+			},
+		};
+		let (targets, generated_paths): (Vec<WatchTarget>, Vec<PathBuf>) = get_install_targets(&myths, cwd, &workspace, &mpm_packages);
+		watcher.set_targets(targets, generated_paths)?;
+
+		if !mpm_packages.is_empty() {
+			match install(mpm_packages, &workspace, options).await {
+				Ok(0) => tracing::info!("installed"),
+				Ok(exit_code) => tracing::error!(exit_code, "install failed with exit code {}", exit_code),
+				Err(e) => report_error(&e),
+			}
+		}
+		tracing::info!("watching for changes");
+		let changed_paths: Vec<PathBuf> = tokio::task::block_in_place(|| watcher.wait_for_change())?;
+		tracing::info!("{} changed, reinstalling", describe_changes(&changed_paths));
+	}
+}
+
+async fn run(args: Args) -> Result<()> {
 	match args.command {
-		MPMCommand::Install { 
-			myth, 
+		MPMCommand::Install {
+			myth,
+			offline,
+			deny_deprecated,
+			frozen,
+			watch,
+			message_format,
 		} => {
-			let cwd = env::current_dir().unwrap();
-			let cwd_path: &Path = cwd.as_path();
-			let mut mpm_packages: Vec<MPMPackage> = Vec::new();
-			if myth.is_some(){
-				let package_path = myth.unwrap();
-				let mpm_package_opt = find_package(package_path.as_path());
-				let mpm_package = mpm_package_opt.expect(format!("couldn't find '{}' at '{}'", FILE_NAME_STRING, package_path.to_str().unwrap()).as_str());
-				mpm_packages.insert(mpm_packages.len(), mpm_package);
-			}else{
-				println!("Searching for muse-package.toml's");
-				mpm_packages = search_for_packages(cwd_path);
-			}
-
-
-			let mut source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>> = HashMap::new();
-			let cache_path_buff: PathBuf = cwd_path.join(CACHE_DIR_NAME);
-			let cache_path: &Path = cache_path_buff.as_path();
-			if cache_path.exists(){
-				for dir_entry in fs::read_dir(cache_path).unwrap() {
-					let dir_entry: fs::DirEntry = dir_entry.unwrap();
-					let dir_path: PathBuf = dir_entry.path();
-					let dir_name: String = decode_path_safe_b64_to_str(&dir_path.file_name().unwrap().to_str().unwrap());
-					let source_url_key: PathBuf = PathBuf::from_str(&dir_name).unwrap();
-
-					// println!("dir_name={}", source_url_key.to_str().unwrap());
-
-					if dir_path.is_dir(){
-						let mut version_cache: HashMap<Version, PackageSourceContent> = HashMap::new();
-						for file_entry in fs::read_dir(dir_path).unwrap() {
-							let file_entry: fs::DirEntry = file_entry.unwrap();
-							let file_path: PathBuf = file_entry.path();
-							let file_name = file_path.file_stem().unwrap().to_str().unwrap();
-							// println!("file_name={}", file_name);
-							let version_name: String = decode_path_safe_b64_to_str(file_name);
-							let version: Version = Version::parse(&version_name).unwrap();
-							let mut file: fs::File = fs::File::open(file_path).unwrap();
-							let mut buffer: Vec<u8> = Vec::new();
-							file.read_to_end(&mut buffer).unwrap();
-
-							let data: bytes::Bytes = bytes::Bytes::from(buffer);
-							let source_url = source_url_key.clone();
-							version_cache.insert(version.clone(), PackageSourceContent{
-								data,
-								version,
-								source_url
-							});
-							// println!("version_name={}", version_name);
-						}
-						source_cache.insert(source_url_key, version_cache);
-					}
+			if matches!(message_format, OutputFormat::Json) {
+				events::enable();
+			}
+			let options: SolveOptions = SolveOptions {
+				offline,
+				deny_deprecated,
+			};
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			if watch {
+				return watch_install(myth, &cwd, options).await;
+			}
+			if myth.is_empty() {
+				tracing::info!("searching for {} files", FILE_NAME_STRING);
+			}
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let mpm_packages: Vec<MPMPackage> = find_myths(&myth, &cwd, &workspace)?;
+			if frozen {
+				return check_myths(mpm_packages, &workspace).await;
+			}
 
-				}
+			let exit_code: i32 = install(mpm_packages, &workspace, options).await?;
+			if exit_code != 0 {
+				std::process::exit(exit_code);
 			}
-			for mpm_package in mpm_packages {
-				source_cache = mpm_package.solve(source_cache).await;
+		},
+		MPMCommand::Check {
+			myth,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let mpm_packages: Vec<MPMPackage> = find_myths(&myth, &cwd, &workspace)?;
+			check_myths(mpm_packages, &workspace).await?;
+		},
+		MPMCommand::Tree {
+			myth,
+			format,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let mpm_packages: Vec<MPMPackage> = find_myths(&myth, &cwd, &workspace)?;
+			let mut source_cache: SourceCache = load_source_cache(&workspace.get_cache_path(), UserConfig::load(&workspace)?)?;
+			source_cache.is_read_only = true;
+			let myth_nodes: Vec<MythNode> = build_tree(&mpm_packages, &mut source_cache)?;
+			match format {
+				OutputFormat::Text => print!("{}", render_tree(&myth_nodes)),
+				OutputFormat::Json => println!("{}", to_json(&myth_nodes)),
 			}
-			if cache_path.exists() == false{
-				let _ = fs::create_dir_all(cache_path).unwrap();
+		},
+		MPMCommand::Why {
+			target,
+			myth,
+			format,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let mpm_packages: Vec<MPMPackage> = find_myths(&myth, &cwd, &workspace)?;
+			let mut source_cache: SourceCache = load_source_cache(&workspace.get_cache_path(), UserConfig::load(&workspace)?)?;
+			source_cache.is_read_only = true;
+			let myth_nodes: Vec<MythNode> = build_tree(&mpm_packages, &mut source_cache)?;
+			let paths: Vec<DependencyPath> = find_paths(&myth_nodes, &target);
+			match format {
+				OutputFormat::Text if paths.is_empty() => println!("nothing depends on '{}'", target),
+				OutputFormat::Text => print!("{}", render_paths(&paths)),
+				OutputFormat::Json => println!("{}", to_json(&paths)),
 			}
-			for (path_buf, version_cache) in source_cache {
-				let dir_name: String = encode_str_to_path_safe_b64(path_buf.to_str().unwrap().to_string());
-				let dir_name_path: PathBuf = PathBuf::from_str(&dir_name).unwrap();
-				let sub_dir_path: PathBuf = cache_path.to_path_buf().join(dir_name_path);
-				if sub_dir_path.exists() == false{
-					let _ = fs::create_dir_all(sub_dir_path.clone()).unwrap();
-				}
-				for (version, psc) in version_cache {
-					let file_name: String = encode_str_to_path_safe_b64(version.to_string());
-					let mut file_name_path: PathBuf = PathBuf::from_str(&file_name).unwrap();
-					file_name_path.set_extension("zip");
-					let full_file_path: PathBuf = sub_dir_path.clone().join(file_name_path);
-					fs::write(full_file_path, psc.data).expect("write fail");
-				}
+		},
+		MPMCommand::Outdated {
+			myth,
+			format,
+			exit_code,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let mpm_packages: Vec<MPMPackage> = find_myths(&myth, &cwd, &workspace)?;
+			let (entries, failures): (Vec<OutdatedEntry>, Vec<MpmError>) = check_outdated(&mpm_packages, &UserConfig::load(&workspace)?).await;
+			match format {
+				OutputFormat::Text => print!("{}", render_table(&entries)),
+				OutputFormat::Json => println!("{}", to_json(&entries)),
 			}
 
+			// a dependency that couldn't be checked is worse than one that's behind
+			let mut failure_code: i32 = 0;
+			for failure in &failures {
+				report_error(failure);
+				failure_code = failure_code.max(failure.exit_code());
+			}
+			if failure_code != 0 {
+				std::process::exit(failure_code);
+			}
+			if exit_code && entries.iter().any(|entry| entry.is_outdated) {
+				std::process::exit(1);
+			}
 		},
-		MPMCommand::Build { 
-			input, 
-			output 
+		MPMCommand::Init {
+			dir,
+			detect,
 		} => {
-			// let source_namespace_name: String = input.file_stem().unwrap().to_str().unwrap().to_string();
-			let target_namespace_name: String = output.file_stem().unwrap().to_str().unwrap().to_string();
-			let mut scripts: HashMap<String, String> = HashMap::new();
-
-			println!("Building from {:?} to {:?}", input, output);
-			for entry in fs::read_dir(input).unwrap() {
-				let entry = entry.unwrap();
-				let path = entry.path();
-				
-				// Ensure the entry is a file
-				if path.is_file() {
-					// Get the file name as a String
-					if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-						// Read the file's contents into a String
-						let contents: String = fs::read_to_string(&path).unwrap();
-						// Insert the file name and contents into the map
-						scripts.insert(name.to_owned(), contents);
+			let myth_dir: PathBuf = match dir {
+				Some(myth_dir) => myth_dir,
+				None => env::current_dir().map_err(|e| MpmError::io("read", ".", e))?,
+			};
+			let manifest_path: PathBuf = myth_dir.join(FILE_NAME_STRING);
+			if manifest_path.exists() {
+				return Err(MpmError::ManifestExists {
+					path: manifest_path,
+				});
+			}
+
+			let mut detected: Vec<(String, ScriptSource)> = Vec::new();
+			if detect {
+				for entry in fs::read_dir(&myth_dir).map_err(|e| MpmError::io("read", &myth_dir, e))? {
+					let path: PathBuf = entry.map_err(|e| MpmError::io("read", &myth_dir, e))?.path();
+					if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("cs") {
+						continue;
+					}
+					let name: String = match path.file_stem().and_then(|n| n.to_str()) {
+						Some(name) => name.to_string(),
+						None => continue,
+					};
+					let contents: String = fs::read_to_string(&path).map_err(|e| MpmError::io("read", &path, e))?;
+					if let Some(script_source) = parse_source_header(&contents) {
+						tracing::info!(dependency = name, url = script_source.url, "found {} from {}", name, script_source.url);
+						detected.push((name, script_source));
 					}
 				}
+				// keep the manifest stable no matter the directory listing order
+				detected.sort_by(|a, b| a.0.cmp(&b.0));
+			}
+
+			fs::write(&manifest_path, render_new_manifest(&detected)).map_err(|e| MpmError::io("write", &manifest_path, e))?;
+			tracing::info!(path = %manifest_path.display(), "created {}", manifest_path.display());
+		},
+		MPMCommand::Add {
+			name,
+			repo,
+			path,
+			version,
+			asset,
+			myth,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let user_config: UserConfig = UserConfig::load(&workspace)?;
+			let myth_dir: PathBuf = find_myth_dir(myth, &cwd, &workspace)?;
+			let manifest_path: PathBuf = find_manifest_path(&myth_dir)?;
+
+			let repo_url: String = match repo.contains("://") {
+				true => repo.trim_end_matches('/').to_string(),
+				false => format!("{}/{}", DEFAULT_WEB_URL, repo.trim_matches('/')),
+			};
+			let requirement: String = version.unwrap_or(String::from("*"));
+			let version_req: VersionReq = VersionReq::parse(&requirement).map_err(|e| MpmError::InvalidVersionReq {
+				url: repo_url.clone(),
+				requirement: requirement.clone(),
+				source: e,
+			})?;
+
+			tracing::info!(source_url = repo_url, "looking up releases of {}", repo_url);
+			let (release_tag, release_version): (String, Version) = find_release(&repo_url, &version_req, &user_config).await?;
+			let mut url: String = format!("{}/releases/tag/{}", repo_url, release_tag);
+			if let Some(inner_path) = &path {
+				url = format!("{}/{}", url, inner_path.trim_matches('/'));
 			}
-			let content: String = compile_to_single_script(
-				String::from("DO NOT EDIT!\n// Compiled using 'github.com/nightcycle/muse-package-manager'"),
-				target_namespace_name, 
-				scripts
-			);
-			if output.exists(){
-				fs::remove_file(output.clone()).expect("bad remove");
+
+			let is_replaced: bool = add_dependency(&manifest_path, &name, &url, asset.as_deref())?;
+			tracing::info!(dependency = name, version = release_version.to_string(), "{} {} {} in {}", if is_replaced { "updated" } else { "added" }, name, release_version, manifest_path.display());
+
+			// only the new dependency is installed, the rest of the myth is left as it was
+			let mut mpm_package: MPMPackage = find_package(&myth_dir, &workspace)?;
+			mpm_package.dependencies.retain(|dependency| dependency.name == name);
+
+			let cache_path_buf: PathBuf = workspace.get_cache_path();
+			let mut source_cache: SourceCache = load_source_cache(&cache_path_buf, user_config)?;
+			let resolution: Resolution<WrittenFile> = mpm_package.solve(&mut source_cache, SolveOptions::default()).await;
+			save_source_cache(&cache_path_buf, &source_cache)?;
+			tracing::info!("{}", summarize(&resolution.resolved));
+			if let Some(e) = resolution.into_error() {
+				return Err(e);
 			}
+		},
+		MPMCommand::Remove {
+			name,
+			myth,
+		} => {
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let myth_dir: PathBuf = find_myth_dir(myth, &cwd, &workspace)?;
+			let manifest_path: PathBuf = find_manifest_path(&myth_dir)?;
+			remove_dependency(&manifest_path, &name)?;
 
-			fs::write(&output, content).expect("Unable to write to output file");
+			let script_path: PathBuf = myth_dir.join(format!("{}.cs", name));
+			if script_path.exists() {
+				fs::remove_file(&script_path).map_err(|e| MpmError::io("remove", &script_path, e))?;
+			}
+			tracing::info!(dependency = name, "removed {} from {}", name, manifest_path.display());
+		},
+		MPMCommand::Build {
+			input,
+			output,
+			include,
+			exclude,
+			watch,
+			message_format,
+		} => {
+			if matches!(message_format, OutputFormat::Json) {
+				events::enable();
+			}
+			let cwd: PathBuf = env::current_dir().map_err(|e| MpmError::io("read", ".", e))?;
+			let workspace: Workspace = Workspace::find(&cwd)?;
+			let (filter, compile_options): (ScriptFilter, CompileOptions) = get_build_options(&input, include, exclude, &workspace)?;
+			if watch {
+				return watch_build(&input, &output, &filter, &compile_options).await;
+			}
+			let written_file: WrittenFile = build(&input, &output, &filter, &compile_options)?;
+			print_status(&summarize(&[written_file]));
 		},
 	}
+	return Ok(());
+}
+
+#[tokio::main]
+async fn main() {
+	let args: Args = Args::parse();
+	let max_level: LevelFilter = match (args.quiet, args.verbose) {
+		(true, _) => LevelFilter::WARN,
+		(false, 0) => LevelFilter::INFO,
+		(false, 1) => LevelFilter::DEBUG,
+		(false, _) => LevelFilter::TRACE,
+	};
+	logging::init(max_level, matches!(args.log_format, OutputFormat::Json));
+
+	if let Err(e) = run(args).await {
+		report_error(&e);
+		std::process::exit(e.exit_code());
+	}
 }
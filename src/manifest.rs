@@ -0,0 +1,602 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use toml_edit::{DocumentMut, ImDocument, InlineTable, Item, TableLike, TomlError, Value};
+use super::diagnostic::{Diagnostic, suggest};
+use super::package_source::ScriptSource;
+use super::collect::{Feature, ScriptFilter, validate_glob, validate_define};
+use super::csharp_parse::CompileOptions;
+use super::workspace::validate_namespace_template;
+use super::error::{MpmError, Result};
+
+/// What `mpm init` writes, commented so it doubles as a reference for the format
+const NEW_MANIFEST_TEMPLATE: &str = r#"# Dependencies of this myth, fetched and compiled by `mpm install`.
+# Each one becomes a single <Name>.cs script next to this file, inside the <Name> namespace.
+#
+# Name = "https://github.com/<owner>/<repo>/releases/tag/<version>/<path to the scripts>"
+# Name = { url = "https://github.com/<owner>/<repo>/releases/tag/<version>", asset = "<release asset>.zip" }
+#
+# `mpm add Name <owner>/<repo> --path <path to the scripts>` writes these for you.
+#
+# A package shared with others can warn anyone installing it that it's been replaced:
+# deprecated = { message = "use OptionV2 instead", replacement = "https://github.com/..." }
+#
+# The namespace can be changed for every dependency here, or for the whole map in its mpm.toml:
+# namespace = "{myth}.{name}"
+#
+# Every .cs file below a package's directory is compiled, other than those its .gitignore or .mpmignore files list.
+# A package can narrow that further:
+# include = ["src/**/*.cs"]
+# exclude = ["**/*Test.cs"]
+#
+# Optional parts of a package, which dependencies turn on with `features = ["tween"]`:
+# [features]
+# tween = { files = ["Tween/**"], defines = ["UI_TWEEN"] }
+
+[dependencies]
+"#;
+
+pub const TOP_LEVEL_KEYS: [&str; 7] = ["deprecated", "include", "exclude", "namespace", "compiler", "features", "dependencies"];
+pub const DEPENDENCY_KEYS: [&str; 4] = ["url", "asset", "path", "features"];
+pub const DEPRECATED_KEYS: [&str; 2] = ["message", "replacement"];
+pub const COMPILER_KEYS: [&str; 2] = ["header", "defines"];
+pub const FEATURE_KEYS: [&str; 2] = ["files", "defines"];
+
+/// Why a package shouldn't be used anymore, and what to use instead
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Deprecation {
+	pub message: Option<String>,
+	/// Usually the release URL of the package that supersedes it
+	pub replacement: Option<String>,
+}
+
+/// A dependency is either a bare release URL, a table naming a release asset to download instead of the zipball,
+/// or a table with the `path` of a package directory on disk. Exactly one of `url` and `path` is set
+#[derive(Debug, Clone)]
+pub struct RawDependency {
+	pub name: String,
+	pub url: Option<String>,
+	pub asset: Option<String>,
+	/// Relative to the manifest's directory
+	pub path: Option<String>,
+	/// Names of the package's optional features to compile in
+	pub features: Vec<String>,
+	/// Byte span of the url or path string in the manifest
+	pub url_span: Option<Range<usize>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawMPMConfig {
+	/// Set when the manifest says `deprecated = true`, `deprecated = "message"` or `deprecated = { message, replacement }`
+	pub deprecated: Option<Deprecation>,
+	/// The `include` and `exclude` globs picking which of the package's files get compiled
+	pub filter: ScriptFilter,
+	/// Replaces the workspace's namespace template for this myth's dependencies
+	pub namespace: Option<String>,
+	/// Replaces the workspace's compiler options for this myth's dependencies, each on its own
+	pub compiler: CompileOptions,
+	/// Optional parts of the package by name, only compiled for dependencies that ask for them
+	pub features: BTreeMap<String, Feature>,
+	/// In the order they're written
+	pub dependencies: Vec<RawDependency>,
+}
+
+fn get_key_span(table: &dyn TableLike, key: &str) -> Option<Range<usize>> {
+	return table.get_key_value(key).and_then(|(key, _)| key.span());
+}
+
+fn unknown_key(path: &Path, contents: &str, table: &dyn TableLike, key: &str, dotted_key: String, known_keys: &[&str]) -> Diagnostic {
+	let help_opt: Option<String> = match suggest(key, known_keys) {
+		Some(suggestion) => Some(format!("did you mean `{}`?", suggestion)),
+		None => Some(format!("expected one of {}", known_keys.iter().map(|k| format!("`{}`", k)).collect::<Vec<String>>().join(", "))),
+	};
+	return Diagnostic::new(path, format!("unknown key `{}`", key))
+		.with_span(contents, get_key_span(table, key))
+		.with_key(dotted_key)
+		.with_help(help_opt);
+}
+
+fn syntax_error(path: &Path, contents: &str, e: &TomlError) -> Diagnostic {
+	let message: String = e.message().trim().lines().collect::<Vec<&str>>().join(", ");
+	return Diagnostic::new(path, message).with_span(contents, e.span());
+}
+
+fn wrong_type(path: &Path, contents: &str, item: &Item, dotted_key: String, expected: &str) -> Diagnostic {
+	return Diagnostic::new(path, format!("expected {}, found {}", expected, item.type_name()))
+		.with_span(contents, item.span())
+		.with_key(dotted_key);
+}
+
+/// Points out the usual ways a release URL gets mangled when copied from GitHub
+pub fn suggest_url_fix(url: &str) -> Option<String> {
+	if url.contains("/releases/tag/") {
+		return None;
+	}
+	for wrong_segment in ["/tree/", "/blob/", "/releases/download/"] {
+		if url.contains(wrong_segment) {
+			return Some(format!("did you mean `{}`?", url.replacen(wrong_segment, "/releases/tag/", 1)));
+		}
+	}
+	if url.contains("/releases/") {
+		return Some(format!("did you mean `{}`?", url.replacen("/releases/", "/releases/tag/", 1)));
+	}
+	if url.contains("/tag/") {
+		return Some(format!("did you mean `{}`?", url.replacen("/tag/", "/releases/tag/", 1)));
+	}
+	return Some(String::from("dependency URLs look like `https://github.com/<owner>/<repo>/releases/tag/<version>/<path>`"));
+}
+
+fn parse_dependency(path: &Path, contents: &str, name: &str, name_span: Option<Range<usize>>, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> Option<RawDependency> {
+	let dotted_key: String = format!("dependencies.{}", name);
+	if let Some(url) = item.as_str() {
+		return Some(RawDependency {
+			name: name.to_string(),
+			url: Some(url.to_string()),
+			asset: None,
+			path: None,
+			features: Vec::new(),
+			url_span: item.span(),
+		});
+	}
+
+	let table: &dyn TableLike = match item.as_table_like() {
+		Some(table) => table,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, dotted_key, "a release URL string or a table"));
+			return None;
+		},
+	};
+
+	let mut is_valid: bool = true;
+	for (key, _) in table.iter() {
+		if !DEPENDENCY_KEYS.contains(&key) {
+			diagnostics.push(unknown_key(path, contents, table, key, format!("{}.{}", dotted_key, key), &DEPENDENCY_KEYS));
+			is_valid = false;
+		}
+	}
+
+	let mut get_string = |key: &str| -> Option<(String, Option<Range<usize>>)> {
+		let value_item: &Item = table.get(key)?;
+		match value_item.as_str() {
+			Some(value) => return Some((value.to_string(), value_item.span())),
+			None => {
+				diagnostics.push(wrong_type(path, contents, value_item, format!("{}.{}", dotted_key, key), "a string"));
+				is_valid = false;
+				return None;
+			},
+		}
+	};
+	let url_opt: Option<(String, Option<Range<usize>>)> = get_string("url");
+	let asset_opt: Option<(String, Option<Range<usize>>)> = get_string("asset");
+	let path_opt: Option<(String, Option<Range<usize>>)> = get_string("path");
+	let features: Vec<String> = table.get("features")
+		.map(|features_item| parse_strings(path, contents, &format!("{}.features", dotted_key), features_item, "feature names", diagnostics))
+		.unwrap_or_default();
+
+	let problem_opt: Option<(&str, Option<String>)> = match (&url_opt, &path_opt) {
+		(Some(_), Some(_)) => Some(("can't have both a `url` and a `path`", None)),
+		(None, Some(_)) if asset_opt.is_some() => Some(("can't have an `asset` without a `url`", None)),
+		(None, None) if is_valid => Some(("has no `url`", Some(String::from("add `url = \"https://github.com/<owner>/<repo>/releases/tag/<version>\"`, or `path = \"<package directory>\"` for one on disk")))),
+		_ => None,
+	};
+	if let Some((problem, help_opt)) = problem_opt {
+		diagnostics.push(
+			Diagnostic::new(path, format!("dependency `{}` {}", name, problem))
+				.with_span(contents, name_span)
+				.with_key(dotted_key.clone())
+				.with_help(help_opt)
+		);
+		is_valid = false;
+	}
+
+	if !is_valid {
+		return None;
+	}
+	let url_span: Option<Range<usize>> = url_opt.as_ref().or(path_opt.as_ref()).and_then(|(_, span)| span.clone());
+	return Some(RawDependency {
+		name: name.to_string(),
+		url: url_opt.map(|(url, _)| url),
+		asset: asset_opt.map(|(asset, _)| asset),
+		path: path_opt.map(|(path, _)| path),
+		features,
+		url_span,
+	});
+}
+
+fn parse_deprecated(path: &Path, contents: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> Option<Deprecation> {
+	if let Some(is_deprecated) = item.as_bool() {
+		return if is_deprecated { Some(Deprecation::default()) } else { None };
+	}
+	if let Some(message) = item.as_str() {
+		return Some(Deprecation {
+			message: Some(message.to_string()),
+			replacement: None,
+		});
+	}
+	let table: &dyn TableLike = match item.as_table_like() {
+		Some(table) => table,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, String::from("deprecated"), "a boolean, a message or a table"));
+			return None;
+		},
+	};
+
+	let mut deprecation: Deprecation = Deprecation::default();
+	for (key, value) in table.iter() {
+		let field: &mut Option<String> = match key {
+			"message" => &mut deprecation.message,
+			"replacement" => &mut deprecation.replacement,
+			_ => {
+				diagnostics.push(unknown_key(path, contents, table, key, format!("deprecated.{}", key), &DEPRECATED_KEYS));
+				continue;
+			},
+		};
+		match value.as_str() {
+			Some(text) => *field = Some(text.to_string()),
+			None => diagnostics.push(wrong_type(path, contents, value, format!("deprecated.{}", key), "a string")),
+		}
+	}
+	return Some(deprecation);
+}
+
+/// An array of strings, like a dependency's features. `expected` describes them for the error when it's something else
+fn parse_strings(path: &Path, contents: &str, key: &str, item: &Item, expected: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<String> {
+	let array: &toml_edit::Array = match item.as_array() {
+		Some(array) => array,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, key.to_string(), &format!("an array of {}", expected)));
+			return Vec::new();
+		},
+	};
+	let mut values: Vec<String> = Vec::new();
+	for value in array.iter() {
+		match value.as_str() {
+			Some(text) => values.push(text.to_string()),
+			None => diagnostics.push(
+				Diagnostic::new(path, format!("expected a string, found {}", value.type_name()))
+					.with_span(contents, value.span())
+					.with_key(key.to_string())
+			),
+		}
+	}
+	return values;
+}
+
+/// `#define` symbols, which have to be plain identifiers
+fn parse_defines(path: &Path, contents: &str, key: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> Vec<String> {
+	let mut defines: Vec<String> = Vec::new();
+	for define in parse_strings(path, contents, key, item, "symbols", diagnostics) {
+		match validate_define(&define) {
+			Ok(()) => defines.push(define),
+			Err(reason) => diagnostics.push(
+				Diagnostic::new(path, format!("`{}` can't be defined, {}", define, reason))
+					.with_span(contents, item.span())
+					.with_key(key.to_string())
+			),
+		}
+	}
+	return defines;
+}
+
+fn parse_compiler(path: &Path, contents: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> CompileOptions {
+	let mut compile_options: CompileOptions = CompileOptions::default();
+	let table: &dyn TableLike = match item.as_table_like() {
+		Some(table) => table,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, String::from("compiler"), "a table"));
+			return compile_options;
+		},
+	};
+	for (key, value) in table.iter() {
+		match key {
+			"header" => match value.as_str() {
+				Some(text) => compile_options.header = Some(text.to_string()),
+				None => diagnostics.push(wrong_type(path, contents, value, String::from("compiler.header"), "a string")),
+			},
+			"defines" => compile_options.defines = parse_defines(path, contents, "compiler.defines", value, diagnostics),
+			_ => diagnostics.push(unknown_key(path, contents, table, key, format!("compiler.{}", key), &COMPILER_KEYS)),
+		}
+	}
+	return compile_options;
+}
+
+fn parse_features(path: &Path, contents: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> BTreeMap<String, Feature> {
+	let mut features: BTreeMap<String, Feature> = BTreeMap::new();
+	let features_table: &dyn TableLike = match item.as_table_like() {
+		Some(table) => table,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, String::from("features"), "a table"));
+			return features;
+		},
+	};
+	for (name, feature_item) in features_table.iter() {
+		let dotted_key: String = format!("features.{}", name);
+		let table: &dyn TableLike = match feature_item.as_table_like() {
+			Some(table) => table,
+			None => {
+				diagnostics.push(wrong_type(path, contents, feature_item, dotted_key, "a table with `files` or `defines`"));
+				continue;
+			},
+		};
+		let mut feature: Feature = Feature::default();
+		for (key, value) in table.iter() {
+			let key_path: String = format!("{}.{}", dotted_key, key);
+			match key {
+				"files" => feature.files = parse_globs(path, contents, &key_path, value, diagnostics),
+				"defines" => feature.defines = parse_defines(path, contents, &key_path, value, diagnostics),
+				_ => diagnostics.push(unknown_key(path, contents, table, key, key_path, &FEATURE_KEYS)),
+			}
+		}
+		features.insert(name.to_string(), feature);
+	}
+	return features;
+}
+
+fn parse_namespace(path: &Path, contents: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> Option<String> {
+	let template: &str = match item.as_str() {
+		Some(template) => template,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, String::from("namespace"), "a string"));
+			return None;
+		},
+	};
+	if let Err(reason) = validate_namespace_template(template) {
+		diagnostics.push(
+			Diagnostic::new(path, format!("`{}` can't be used as a namespace, {}", template, reason))
+				.with_span(contents, item.span())
+				.with_key("namespace")
+		);
+		return None;
+	}
+	return Some(template.to_string());
+}
+
+fn parse_globs(path: &Path, contents: &str, key: &str, item: &Item, diagnostics: &mut Vec<Diagnostic>) -> Vec<String> {
+	let array: &toml_edit::Array = match item.as_array() {
+		Some(array) => array,
+		None => {
+			diagnostics.push(wrong_type(path, contents, item, key.to_string(), "an array of globs"));
+			return Vec::new();
+		},
+	};
+	let mut patterns: Vec<String> = Vec::new();
+	for value in array.iter() {
+		let pattern: &str = match value.as_str() {
+			Some(pattern) => pattern,
+			None => {
+				diagnostics.push(
+					Diagnostic::new(path, format!("expected a glob string, found {}", value.type_name()))
+						.with_span(contents, value.span())
+						.with_key(key.to_string())
+				);
+				continue;
+			},
+		};
+		match validate_glob(pattern) {
+			Ok(()) => patterns.push(pattern.to_string()),
+			Err(reason) => diagnostics.push(
+				Diagnostic::new(path, format!("`{}` is not a valid glob: {}", pattern, reason))
+					.with_span(contents, value.span())
+					.with_key(key.to_string())
+			),
+		}
+	}
+	return patterns;
+}
+
+impl RawMPMConfig {
+	/// Parses a manifest, collecting every problem in it rather than stopping at the first.
+	/// Whatever could be read is still returned so callers can keep checking it.
+	pub fn parse(path: &Path, contents: &str) -> (Self, Vec<Diagnostic>) {
+		let document: ImDocument<&str> = match ImDocument::parse(contents) {
+			Ok(document) => document,
+			Err(e) => {
+				let empty_config: RawMPMConfig = RawMPMConfig {
+					deprecated: None,
+					filter: ScriptFilter::default(),
+					namespace: None,
+					compiler: CompileOptions::default(),
+					features: BTreeMap::new(),
+					dependencies: Vec::new(),
+				};
+				return (empty_config, vec![syntax_error(path, contents, &e)]);
+			},
+		};
+		let root: &dyn TableLike = document.as_table();
+		let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+		for (key, _) in root.iter() {
+			if !TOP_LEVEL_KEYS.contains(&key) {
+				diagnostics.push(unknown_key(path, contents, root, key, key.to_string(), &TOP_LEVEL_KEYS));
+			}
+		}
+
+		let deprecated: Option<Deprecation> = root.get("deprecated")
+			.and_then(|deprecated_item| parse_deprecated(path, contents, deprecated_item, &mut diagnostics));
+
+		let filter: ScriptFilter = ScriptFilter {
+			include: root.get("include").map(|item| parse_globs(path, contents, "include", item, &mut diagnostics)).unwrap_or_default(),
+			exclude: root.get("exclude").map(|item| parse_globs(path, contents, "exclude", item, &mut diagnostics)).unwrap_or_default(),
+		};
+
+		let namespace: Option<String> = root.get("namespace")
+			.and_then(|namespace_item| parse_namespace(path, contents, namespace_item, &mut diagnostics));
+		let compiler: CompileOptions = root.get("compiler")
+			.map(|compiler_item| parse_compiler(path, contents, compiler_item, &mut diagnostics))
+			.unwrap_or_default();
+		let features: BTreeMap<String, Feature> = root.get("features")
+			.map(|features_item| parse_features(path, contents, features_item, &mut diagnostics))
+			.unwrap_or_default();
+
+		let mut dependencies: Vec<RawDependency> = Vec::new();
+		if let Some(dependencies_item) = root.get("dependencies") {
+			match dependencies_item.as_table_like() {
+				Some(dependencies_table) => {
+					for (name, item) in dependencies_table.iter() {
+						let name_span: Option<Range<usize>> = get_key_span(dependencies_table, name);
+						if let Some(dependency) = parse_dependency(path, contents, name, name_span, item, &mut diagnostics) {
+							dependencies.push(dependency);
+						}
+					}
+				},
+				None => diagnostics.push(wrong_type(path, contents, dependencies_item, String::from("dependencies"), "a table")),
+			}
+		}
+
+		let raw_config: RawMPMConfig = RawMPMConfig {
+			deprecated,
+			filter,
+			namespace,
+			compiler,
+			features,
+			dependencies,
+		};
+		return (raw_config, diagnostics);
+	}
+}
+
+/// Reads a manifest for editing, keeping its comments and formatting
+fn read_document(path: &Path) -> Result<DocumentMut> {
+	let contents: String = fs::read_to_string(path).map_err(|e| MpmError::io("read", path, e))?;
+	return contents.parse::<DocumentMut>().map_err(|e| MpmError::Manifest {
+		diagnostics: vec![syntax_error(path, &contents, &e)],
+	});
+}
+
+fn write_document(path: &Path, document: &DocumentMut) -> Result<()> {
+	return fs::write(path, document.to_string()).map_err(|e| MpmError::io("write", path, e));
+}
+
+/// Sets a dependency in the manifest at `path`, adding `[dependencies]` if needed. Returns whether an existing entry was replaced
+pub fn add_dependency(path: &Path, name: &str, url: &str, asset: Option<&str>) -> Result<bool> {
+	let mut document: DocumentMut = read_document(path)?;
+	let dependencies_table: &mut dyn TableLike = document.entry("dependencies")
+		.or_insert(toml_edit::table())
+		.as_table_like_mut()
+		.ok_or_else(|| MpmError::Manifest {
+			diagnostics: vec![Diagnostic::new(path, "expected `dependencies` to be a table").with_key("dependencies")],
+		})?;
+
+	let value: Value = match asset {
+		Some(asset_name) => {
+			let mut inline_table: InlineTable = InlineTable::new();
+			inline_table.insert("url", Value::from(url));
+			inline_table.insert("asset", Value::from(asset_name));
+			Value::InlineTable(inline_table)
+		},
+		None => Value::from(url),
+	};
+	let is_replaced: bool = dependencies_table.insert(name, Item::Value(value)).is_some();
+
+	write_document(path, &document)?;
+	return Ok(is_replaced);
+}
+
+/// Deletes a dependency from the manifest at `path`
+pub fn remove_dependency(path: &Path, name: &str) -> Result<()> {
+	let mut document: DocumentMut = read_document(path)?;
+	let removed_opt: Option<Item> = document.get_mut("dependencies")
+		.and_then(|item| item.as_table_like_mut())
+		.and_then(|dependencies_table| dependencies_table.remove(name));
+	if removed_opt.is_none() {
+		return Err(MpmError::DependencyNotFound {
+			path: path.to_path_buf(),
+			name: name.to_string(),
+		});
+	}
+
+	write_document(path, &document)?;
+	return Ok(());
+}
+
+/// Renders the manifest `mpm init` creates, filled in with any dependencies found in existing scripts.
+/// Scripts whose header doesn't say which release they came from are added commented out, to be finished by hand.
+pub fn render_new_manifest(detected: &[(String, ScriptSource)]) -> String {
+	let mut document: DocumentMut = NEW_MANIFEST_TEMPLATE.parse::<DocumentMut>().expect("template is valid");
+	let mut unpinned_lines: Vec<String> = Vec::new();
+	if let Some(dependencies_table) = document.get_mut("dependencies").and_then(|item| item.as_table_like_mut()) {
+		for (name, script_source) in detected {
+			let value: Value = match &script_source.asset {
+				Some(asset_name) => {
+					let mut inline_table: InlineTable = InlineTable::new();
+					inline_table.insert("url", Value::from(script_source.url.as_str()));
+					inline_table.insert("asset", Value::from(asset_name.as_str()));
+					Value::InlineTable(inline_table)
+				},
+				None => Value::from(script_source.url.as_str()),
+			};
+			if script_source.is_pinned {
+				dependencies_table.insert(name, Item::Value(value));
+			} else {
+				unpinned_lines.push(format!("# {} = {} # add the release tag and path", name, value));
+			}
+		}
+	}
+
+	let mut contents: String = document.to_string();
+	for line in unpinned_lines {
+		contents.push_str(&line);
+		contents.push('\n');
+	}
+	return contents;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn release_page_urls_need_no_fix() {
+		assert_eq!(suggest_url_fix("https://github.com/owner/repo/releases/tag/v1.0.0"), None);
+	}
+
+	#[test]
+	fn suggests_the_release_page_for_near_misses() {
+		let expected: String = String::from("did you mean `https://github.com/owner/repo/releases/tag/v1.0.0`?");
+		for url in [
+			"https://github.com/owner/repo/tree/v1.0.0",
+			"https://github.com/owner/repo/blob/v1.0.0",
+			"https://github.com/owner/repo/releases/download/v1.0.0",
+			"https://github.com/owner/repo/releases/v1.0.0",
+			"https://github.com/owner/repo/tag/v1.0.0",
+		] {
+			assert_eq!(suggest_url_fix(url), Some(expected.clone()), "for {}", url);
+		}
+	}
+
+	#[test]
+	fn falls_back_to_the_url_format() {
+		let help: String = suggest_url_fix("https://github.com/owner/repo").expect("a help for an unrecognised URL");
+		assert!(help.contains("/releases/tag/<version>"), "{}", help);
+	}
+
+	#[test]
+	fn reports_every_bad_entry_in_a_manifest() {
+		let contents: &str = concat!(
+			"nmespace = \"Oops\"\n",
+			"\n",
+			"[dependencies]\n",
+			"Signal = 3\n",
+			"Promise = { url = \"https://github.com/owner/promise/releases/tag/v1\", ulr = \"x\" }\n",
+			"Both = { url = \"https://github.com/owner/both/releases/tag/v1\", path = \"../both\" }\n",
+			"Good = \"https://github.com/owner/good/releases/tag/v1\"\n",
+		);
+		let (raw_config, diagnostics): (RawMPMConfig, Vec<Diagnostic>) = RawMPMConfig::parse(Path::new("mpm.toml"), contents);
+
+		let keys: Vec<Option<&str>> = diagnostics.iter().map(|diagnostic| diagnostic.key.as_deref()).collect();
+		assert_eq!(keys, vec![
+			Some("nmespace"),
+			Some("dependencies.Signal"),
+			Some("dependencies.Promise.ulr"),
+			Some("dependencies.Both"),
+		]);
+		assert_eq!(diagnostics[0].help.as_deref(), Some("did you mean `namespace`?"));
+		assert_eq!(diagnostics[2].help.as_deref(), Some("did you mean `url`?"));
+		assert_eq!(diagnostics[3].location.as_ref().map(|location| location.line), Some(6));
+
+		let names: Vec<&str> = raw_config.dependencies.iter().map(|dependency| dependency.name.as_str()).collect();
+		assert_eq!(names, vec!["Good"]);
+	}
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use super::package::MPMPackage;
+use super::user_config::UserConfig;
+use super::package_source::{fetch_releases, select_release, SourceType};
+use super::error::{MpmError, Result};
+
+/// How far behind upstream one dependency of one myth is
+#[derive(Debug, Clone, Serialize)]
+pub struct OutdatedEntry {
+	pub myth: String,
+	pub dependency: String,
+	pub source_url: String,
+	pub version_req: String,
+	/// The version written in the dependency URL
+	pub pinned: String,
+	/// The release an install downloads, the first the requirement accepts in the repo's listing
+	pub compatible: Option<String>,
+	/// Newest release of all, which may need the URL bumped to reach
+	pub latest: Option<String>,
+	/// Whether `latest` is newer than what an install gets, so the URL needs bumping
+	pub is_outdated: bool,
+}
+
+/// The version a requirement was written from, `v0.2` in a URL pins `0.2.0`
+fn get_pinned_version(version_req: &VersionReq) -> Version {
+	match version_req.comparators.first() {
+		Some(comparator) => Version::new(comparator.major, comparator.minor.unwrap_or(0), comparator.patch.unwrap_or(0)),
+		None => Version::new(0, 0, 0),
+	}
+}
+
+/// Compares every dependency against its repo's releases, each repo is only listed once.
+/// Dependencies whose releases couldn't be listed are returned as failures alongside the entries that could.
+pub async fn check_outdated(mpm_packages: &[MPMPackage], user_config: &UserConfig) -> (Vec<OutdatedEntry>, Vec<MpmError>) {
+	let mut releases_by_source: HashMap<String, Vec<(String, Version)>> = HashMap::new();
+	let mut entries: Vec<OutdatedEntry> = Vec::new();
+	let mut failures: Vec<MpmError> = Vec::new();
+
+	for mpm_package in mpm_packages {
+		for mpm_dependency in &mpm_package.dependencies {
+			// path dependencies have no releases to be behind on
+			if mpm_dependency.source.source_type == SourceType::Local {
+				continue;
+			}
+			let source_url: String = mpm_dependency.source.source_url.to_string_lossy().to_string();
+			if !releases_by_source.contains_key(&source_url) {
+				let fetch_result: Result<Vec<(String, Version)>> = fetch_releases(&source_url, user_config).await;
+				match fetch_result {
+					Ok(releases) => {
+						releases_by_source.insert(source_url.clone(), releases);
+					},
+					Err(e) => {
+						failures.push(MpmError::Dependency {
+							myth: mpm_package.name.clone(),
+							dependency: mpm_dependency.name.clone(),
+							source: Box::new(e),
+						});
+						continue;
+					},
+				}
+			}
+			let releases: &Vec<(String, Version)> = &releases_by_source[&source_url];
+
+			let version_req: &VersionReq = &mpm_dependency.source.version_req;
+			let pinned: Version = get_pinned_version(version_req);
+			let compatible_opt: Option<&Version> = select_release(releases, version_req).map(|(_, version)| version);
+			let latest_opt: Option<&Version> = releases.iter().map(|(_, version)| version).max();
+			// only a newer release the URL won't resolve to needs the URL bumped, one it already accepts gets installed anyway
+			let is_outdated: bool = match (latest_opt, compatible_opt) {
+				(Some(latest), Some(compatible)) => latest > compatible,
+				(Some(_), None) => true,
+				(None, _) => false,
+			};
+
+			entries.push(OutdatedEntry {
+				myth: mpm_package.name.clone(),
+				dependency: mpm_dependency.name.clone(),
+				source_url,
+				version_req: version_req.to_string(),
+				pinned: pinned.to_string(),
+				compatible: compatible_opt.map(|version| version.to_string()),
+				latest: latest_opt.map(|version| version.to_string()),
+				is_outdated,
+			});
+		}
+	}
+	return (entries, failures);
+}
+
+/// Lines the entries up in columns, like `cargo outdated` does
+pub fn render_table(entries: &[OutdatedEntry]) -> String {
+	let mut rows: Vec<[String; 6]> = vec![[
+		String::from("Myth"),
+		String::from("Dependency"),
+		String::from("Pinned"),
+		String::from("Compatible"),
+		String::from("Latest"),
+		String::from("Source"),
+	]];
+	for entry in entries {
+		rows.push([
+			entry.myth.clone(),
+			entry.dependency.clone(),
+			entry.pinned.clone(),
+			entry.compatible.clone().unwrap_or(String::from("---")),
+			entry.latest.clone().unwrap_or(String::from("---")),
+			entry.source_url.clone(),
+		]);
+	}
+
+	let mut widths: [usize; 6] = [0; 6];
+	for row in &rows {
+		for (width, cell) in widths.iter_mut().zip(row.iter()) {
+			*width = (*width).max(cell.chars().count());
+		}
+	}
+
+	let mut output: String = String::new();
+	for row in &rows {
+		let cells: Vec<String> = row.iter().zip(widths.iter())
+			.map(|(cell, width)| format!("{:<width$}", cell, width = width))
+			.collect();
+		output.push_str(cells.join("  ").trim_end());
+		output.push('\n');
+	}
+	return output;
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use tempfile::{Builder, NamedTempFile};
+use super::error::{MpmError, Result};
+
+/// What writing a generated file did to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteOutcome {
+	Created,
+	Updated,
+	Unchanged,
+}
+
+#[derive(Debug, Clone)]
+pub struct WrittenFile {
+	pub path_buf: PathBuf,
+	pub outcome: WriteOutcome,
+}
+
+/// Writes `contents` to `path` unless it already holds exactly that, so untouched files keep their mtime.
+/// Changes go through a temporary file in the same directory that's renamed over the old one, so the file is never half written.
+/// An updated file keeps its permissions, a new one gets the same as any other file the user creates.
+pub fn write_if_changed(path: &Path, contents: &str) -> Result<WrittenFile> {
+	let mut permissions: Option<fs::Permissions> = None;
+	let outcome: WriteOutcome = match fs::read(path) {
+		Ok(current) if current == contents.as_bytes() => WriteOutcome::Unchanged,
+		Ok(_) => {
+			permissions = Some(fs::metadata(path).map_err(|e| MpmError::io("read", path, e))?.permissions());
+			WriteOutcome::Updated
+		},
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => WriteOutcome::Created,
+		Err(e) => return Err(MpmError::io("read", path, e)),
+	};
+
+	if outcome != WriteOutcome::Unchanged {
+		let dir_path: &Path = match path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+		let mut temp_file: NamedTempFile = new_temp_file(dir_path).map_err(|e| MpmError::io("create", dir_path, e))?;
+		if let Some(permissions) = permissions {
+			temp_file.as_file().set_permissions(permissions).map_err(|e| MpmError::io("write", temp_file.path().to_path_buf(), e))?;
+		}
+		temp_file.write_all(contents.as_bytes()).map_err(|e| MpmError::io("write", temp_file.path().to_path_buf(), e))?;
+		temp_file.persist(path).map_err(|e| MpmError::io("write", path, e.error))?;
+	}
+
+	return Ok(WrittenFile {
+		path_buf: path.to_path_buf(),
+		outcome,
+	});
+}
+
+/// Temporary files are owner-only by default, which would stick to the file they're renamed into
+#[cfg(unix)]
+fn new_temp_file(dir_path: &Path) -> std::io::Result<NamedTempFile> {
+	use std::os::unix::fs::PermissionsExt;
+	// the umask still applies, same as for a plain `File::create`
+	return Builder::new().permissions(fs::Permissions::from_mode(0o666)).tempfile_in(dir_path);
+}
+
+#[cfg(not(unix))]
+fn new_temp_file(dir_path: &Path) -> std::io::Result<NamedTempFile> {
+	return Builder::new().tempfile_in(dir_path);
+}
+
+/// Lists the created and updated files and counts the unchanged ones
+pub fn summarize(written_files: &[WrittenFile]) -> String {
+	let mut lines: Vec<String> = Vec::new();
+	let mut counts: [usize; 3] = [0; 3];
+	for written_file in written_files {
+		let (index, label): (usize, &str) = match written_file.outcome {
+			WriteOutcome::Created => (0, "created"),
+			WriteOutcome::Updated => (1, "updated"),
+			WriteOutcome::Unchanged => (2, "unchanged"),
+		};
+		counts[index] += 1;
+		if written_file.outcome != WriteOutcome::Unchanged {
+			lines.push(format!("  {} {}", label, written_file.path_buf.display()));
+		}
+	}
+	lines.push(format!("{} created, {} updated, {} unchanged", counts[0], counts[1], counts[2]));
+	return lines.join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::{Duration, SystemTime};
+
+	#[test]
+	fn creates_then_updates() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		let path: PathBuf = temp_dir.path().join("Out.cs");
+		assert_eq!(write_if_changed(&path, "a").expect("the first write").outcome, WriteOutcome::Created);
+		assert_eq!(write_if_changed(&path, "b").expect("the second write").outcome, WriteOutcome::Updated);
+		assert_eq!(fs::read_to_string(&path).expect("the written file"), "b");
+	}
+
+	#[test]
+	fn identical_write_leaves_the_file_alone() {
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		let path: PathBuf = temp_dir.path().join("Out.cs");
+		write_if_changed(&path, "contents").expect("the first write");
+
+		let old_mtime: SystemTime = SystemTime::now() - Duration::from_secs(3600);
+		let file: fs::File = fs::File::options().write(true).open(&path).expect("the written file");
+		file.set_modified(old_mtime).expect("the mtime to be set");
+		drop(file);
+		let mut permissions: fs::Permissions = fs::metadata(&path).expect("the file's metadata").permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&path, permissions.clone()).expect("the permissions to be set");
+
+		assert_eq!(write_if_changed(&path, "contents").expect("the second write").outcome, WriteOutcome::Unchanged);
+		let metadata: fs::Metadata = fs::metadata(&path).expect("the file's metadata");
+		assert_eq!(metadata.modified().expect("the file's mtime"), old_mtime);
+		assert_eq!(metadata.permissions(), permissions);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn update_keeps_the_permissions() {
+		use std::os::unix::fs::PermissionsExt;
+		let temp_dir = tempfile::tempdir().expect("a temp dir");
+		let path: PathBuf = temp_dir.path().join("Out.cs");
+		write_if_changed(&path, "a").expect("the first write");
+		fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).expect("the permissions to be set");
+
+		write_if_changed(&path, "b").expect("the second write");
+		assert_eq!(fs::metadata(&path).expect("the file's metadata").permissions().mode() & 0o777, 0o640);
+	}
+}
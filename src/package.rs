@@ -1,60 +1,147 @@
 use core::str;
 use std::fs;
+use std::collections::HashSet;
 
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
+use globset::GlobSet;
+use super::collect::{build_glob_set, to_relative_key};
 use serde::Deserialize;
 use semver::Version;
-use super::package_source::{PackageSource, PackageSourceContent};
-use std::collections::HashMap;
+use super::package_source::{PackageSource, SourceCache, CompiledSource, SourceType};
+use super::manifest::{RawMPMConfig, Deprecation, suggest_url_fix};
+use super::diagnostic::{Diagnostic, suggest};
+use super::output::{write_if_changed, WrittenFile};
+use super::csharp_parse::CompileOptions;
+use super::workspace::{Workspace, render_namespace, WORKSPACE_FILE_NAME};
+use super::cache::CACHE_DIR_NAME;
+use super::events::{self, Message};
+use super::error::{MpmError, Result};
 
 pub const FILE_NAME_STRING: &str = "muse-package.toml";
 
-#[derive(Debug, Deserialize)]
-struct RawMPMConfig {
-	pub deprecated: Option<bool>,
-	pub dependencies: HashMap<String, String>,
+/// Switches that change how every dependency of an install gets solved
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SolveOptions {
+	/// Resolve strictly from the cache
+	pub offline: bool,
+	/// Fail on deprecated packages instead of warning about them
+	pub deny_deprecated: bool,
 }
 
-impl RawMPMConfig {
-	fn new(config_file_path: &Path ) -> Self {
-		let contents_result: std::prelude::v1::Result<String, std::io::Error> = fs::read_to_string(config_file_path);
-		let contents_sting: String = contents_result.expect("bad path");
-		let contents: &str = contents_sting.as_str();
-		
-		return toml::from_str(contents).expect("bad config file");
-	}
+/// A generated script whose contents differ from what its dependency compiles to
+#[derive(Debug, Clone)]
+pub struct Drift {
+	pub path_buf: PathBuf,
+	/// Unified diff from the file on disk to the expected script
+	pub diff: String,
+}
+
+/// What solving a dependency did
+#[derive(Debug, Clone)]
+pub struct SolvedDependency {
+	pub written_file: WrittenFile,
+	/// The release it resolved to, `None` for path dependencies
+	pub version: Option<Version>,
+	pub deprecation: Option<Deprecation>,
 }
 
+/// What solving or checking a myth's dependencies came to, one failing doesn't stop the others so there can be both
+#[derive(Debug)]
+pub struct Resolution<T> {
+	pub myth: String,
+	pub resolved: Vec<T>,
+	/// One per dependency that couldn't be satisfied
+	pub failures: Vec<MpmError>,
+}
+
+impl<T> Resolution<T> {
+	/// The failures as a single error naming the myth, `None` when every dependency went through
+	pub fn into_error(self: Self) -> Option<MpmError> {
+		if self.failures.is_empty() {
+			return None;
+		}
+		return Some(MpmError::Unsatisfied {
+			myth: self.myth,
+			failures: self.failures,
+		});
+	}
+}
 
 #[derive(Debug, Deserialize)]
 pub struct MPMDependency{
 	pub name: String,
 	pub path_buf: PathBuf,
-	source: PackageSource,
+	pub source: PackageSource,
+	/// What the scripts are compiled into, from the myth's or workspace's namespace template
+	pub namespace: String,
+	#[serde(skip)]
+	pub compile_options: CompileOptions,
 }
 
 impl MPMDependency {
-	fn new(name: String, path_buf: PathBuf, value: String) -> Self {
-		let source: PackageSource = PackageSource::new(value);
+	fn new(name: String, path_buf: PathBuf, value: String, asset: Option<String>, namespace: String, compile_options: CompileOptions) -> Result<Self> {
+		let source: PackageSource = PackageSource::new(value, asset)?;
+		return Ok(MPMDependency {
+			name,
+			path_buf,
+			source,
+			namespace,
+			compile_options,
+		});	
+	}
+	fn new_local(name: String, path_buf: PathBuf, manifest_dir_path: &Path, package_path: String, namespace: String, compile_options: CompileOptions) -> Self {
+		let source: PackageSource = PackageSource::new_local(manifest_dir_path, package_path);
 		return MPMDependency {
 			name,
 			path_buf,
-			source
-		};	
+			source,
+			namespace,
+			compile_options,
+		};
 	}
-	pub async fn solve(self: Self, original_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>>) -> HashMap<PathBuf, HashMap<Version, PackageSourceContent>>{
-		let mut new_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>> = original_source_cache.clone();
-
-		let content: String;
-		(new_source_cache, content) = self.source.solve(self.name, new_source_cache.clone()).await;
-		if self.path_buf.exists(){
-			fs::remove_file(self.path_buf.clone()).expect("remove fail");
+	/// Writes the dependency's script if it changed, also returning its deprecation so the caller can warn about it
+	pub async fn solve(self: Self, myth_path: &Path, source_cache: &mut SourceCache, options: SolveOptions) -> Result<SolvedDependency>{
+		let source_url: String = self.source.source_url.to_string_lossy().to_string();
+		let compiled_source: CompiledSource = self.source.solve(&self.name, myth_path, self.namespace, &self.compile_options, source_cache, options.offline).await?;
+		if let Some(deprecation) = &compiled_source.deprecation {
+			if options.deny_deprecated {
+				return Err(MpmError::Deprecated {
+					source_url,
+					message: deprecation.message.clone(),
+				});
+			}
 		}
+		let written_file: WrittenFile = write_if_changed(&self.path_buf, &compiled_source.script)?;
+		return Ok(SolvedDependency {
+			written_file,
+			version: compiled_source.version,
+			deprecation: compiled_source.deprecation,
+		});
+	}
 
-		fs::write(self.path_buf, content).expect("write fail");
+	/// Compiles the dependency from the cache alone and compares it with the script on disk, nothing is written
+	pub async fn check(self: Self, myth_path: &Path, source_cache: &mut SourceCache) -> Result<Option<Drift>>{
+		let compiled_source: CompiledSource = self.source.solve(&self.name, myth_path, self.namespace, &self.compile_options, source_cache, true).await?;
+		// a script that was never generated is drift too
+		let current: String = match self.path_buf.exists() {
+			true => fs::read_to_string(&self.path_buf).map_err(|e| MpmError::io("read", &self.path_buf, e))?,
+			false => String::new(),
+		};
+		if current == compiled_source.script {
+			return Ok(None);
+		}
 
-		return new_source_cache;
+		let path_name: String = self.path_buf.to_string_lossy().to_string();
+		let diff: String = similar::TextDiff::from_lines(&current, &compiled_source.script)
+			.unified_diff()
+			.context_radius(3)
+			.header(&path_name, &format!("{} (expected)", path_name))
+			.to_string();
+		return Ok(Some(Drift {
+			path_buf: self.path_buf,
+			diff,
+		}));
 	}
 }
 
@@ -62,85 +149,334 @@ impl MPMDependency {
 pub struct MPMPackage {
 	pub name: String,
 	pub config_path_buf: PathBuf,
-	pub is_deprecated: Option<bool>,
+	/// What this package's own manifest says about it being deprecated, only matters to those installing it
+	pub deprecation: Option<Deprecation>,
 	pub dependencies: Vec<MPMDependency>,
 }
 
 impl MPMPackage {
-	fn new(config_file_path: &Path) -> Self {
-		let raw_config: RawMPMConfig = RawMPMConfig::new(config_file_path);
-		let name: String = config_file_path.parent() // Option<&Path>
-			.and_then(|p| p.file_name()) // Option<&OsStr>
-			.and_then(|os_str| os_str.to_str()) // Option<&str>
-			.expect("Failed to extract directory name from path") // Panics if None
-			.to_owned();
+	/// Reads the myth's manifest, filling in whatever it doesn't set from the workspace
+	fn new(config_file_path: &Path, workspace: &Workspace) -> Result<Self> {
+		let contents: String = fs::read_to_string(config_file_path).map_err(|e| MpmError::io("read", config_file_path, e))?;
+		let (raw_config, mut diagnostics): (RawMPMConfig, Vec<Diagnostic>) = RawMPMConfig::parse(config_file_path, &contents);
+		let dir_path_buf: PathBuf = config_file_path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+		let name_opt: Option<String> = get_myth_name(config_file_path);
+		if name_opt.is_none() {
+			diagnostics.push(Diagnostic::new(config_file_path, "couldn't name the myth from its directory"));
+		}
 
-		let is_deprecated: Option<bool> = raw_config.deprecated;
+		let deprecation: Option<Deprecation> = raw_config.deprecated;
+		let myth_name: String = name_opt.clone().unwrap_or_default();
+		let namespace_template: &str = raw_config.namespace.as_deref().unwrap_or(workspace.get_namespace_template());
+		let compile_options: CompileOptions = workspace.config.compiler.clone().overridden_by(raw_config.compiler);
 
 		let mut dependencies: Vec<MPMDependency> = Vec::new();
 
-		let dir_path_buf = config_file_path.parent().unwrap().to_path_buf();
-		for (dep_name, dep_value) in raw_config.dependencies {
-			let dep_file_name: String = format!("{}.cs", dep_name);
+		for raw_dependency in raw_config.dependencies {
+			let dep_file_name: String = format!("{}.cs", raw_dependency.name);
 			let dep_path_buf = dir_path_buf.join(&dep_file_name);
-			let dependency: MPMDependency = MPMDependency::new(
-				dep_name.clone(), 
-				dep_path_buf,
-				dep_value.clone()
-			);
-			dependencies.push(dependency);
+			let namespace: String = render_namespace(namespace_template, &myth_name, &raw_dependency.name);
+			let dependency_result: Result<MPMDependency> = match &raw_dependency.url {
+				Some(url) => MPMDependency::new(
+					raw_dependency.name.clone(), 
+					dep_path_buf,
+					url.clone(),
+					raw_dependency.asset.clone(),
+					namespace,
+					compile_options.clone()
+				),
+				None => {
+					let package_path: &String = raw_dependency.path.as_ref().expect("manifest parsing requires a url or a path");
+					Ok(MPMDependency::new_local(raw_dependency.name.clone(), dep_path_buf, &dir_path_buf, package_path.clone(), namespace, compile_options.clone()))
+				},
+			};
+			match dependency_result {
+				Ok(mut dependency) => {
+					dependency.source.features = raw_dependency.features.clone();
+					// the workspace pins shared dependencies so every myth ends up on the same release
+					if let Some(version_req) = workspace.config.overrides.get(&dependency.name) {
+						if dependency.source.source_type != SourceType::Local {
+							dependency.source.version_req = version_req.clone();
+						}
+					}
+					dependencies.push(dependency);
+				},
+				Err(e) => {
+					let help_opt: Option<String> = match &e {
+						MpmError::InvalidUrl { url, .. } => suggest_url_fix(url),
+						MpmError::InvalidVersionReq { .. } => Some(String::from("the segment after `/tag/` should be a version like `v0.2.0`")),
+						_ => None,
+					};
+					diagnostics.push(
+						Diagnostic::new(config_file_path, e.to_string())
+							.with_span(&contents, raw_dependency.url_span.clone())
+							.with_key(format!("dependencies.{}", raw_dependency.name))
+							.with_help(help_opt)
+					);
+				},
+			}
 		}
+		if !diagnostics.is_empty() {
+			return Err(MpmError::Manifest {
+				diagnostics,
+			});
+		}
+		let name: String = name_opt.unwrap_or_default();
 		let config_path_buf: PathBuf = config_file_path.to_path_buf(); //.to_str().expect("string conversion fail").to_owned().to_string();
 
-		return MPMPackage {
+		return Ok(MPMPackage {
 			name,
 			config_path_buf,
-			is_deprecated,
+			deprecation,
 			dependencies
+		});
+	}
+
+	/// Solves every dependency into the cache, returning the scripts written along with every dependency that couldn't be satisfied once all were tried
+	pub async fn solve(self: Self, source_cache: &mut SourceCache, options: SolveOptions) -> Resolution<WrittenFile>{
+		let mut written_files: Vec<WrittenFile> = Vec::new();
+		let mut failures: Vec<MpmError> = Vec::new();
+
+		for mpm_dependency in self.dependencies{
+			let dep_name: String = mpm_dependency.name.clone();
+			let source_url: String = mpm_dependency.source.source_url.to_string_lossy().to_string();
+			let asset: Option<String> = mpm_dependency.source.asset.clone();
+			match mpm_dependency.solve(&self.config_path_buf, source_cache, options).await {
+				Ok(solved_dependency) => {
+					events::emit(Message::ResolutionChosen {
+						myth_path: self.config_path_buf.clone(),
+						dependency: dep_name.clone(),
+						source_url: source_url.clone(),
+						asset,
+						version: solved_dependency.version.clone(),
+					});
+					if let Some(deprecation) = solved_dependency.deprecation {
+						warn_deprecated(&self.name, &self.config_path_buf, &dep_name, &source_url, &deprecation);
+					}
+					events::emit(Message::FileWritten {
+						myth_path: Some(self.config_path_buf.clone()),
+						dependency: Some(dep_name),
+						output_path: solved_dependency.written_file.path_buf.clone(),
+						outcome: solved_dependency.written_file.outcome,
+						version: solved_dependency.version,
+					});
+					written_files.push(solved_dependency.written_file);
+				},
+				Err(e) => {
+					events::emit(Message::Error {
+						myth_path: Some(self.config_path_buf.clone()),
+						dependency: Some(dep_name.clone()),
+						message: e.to_string(),
+						exit_code: e.exit_code(),
+					});
+					failures.push(MpmError::Dependency {
+						myth: self.name.clone(),
+						dependency: dep_name,
+						source: Box::new(e),
+					});
+				},
+			}
+		}
+
+		return Resolution {
+			myth: self.name,
+			resolved: written_files,
+			failures,
 		};
 	}
 
-	pub async fn solve(self: Self, original_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>>) -> HashMap<PathBuf, HashMap<Version, PackageSourceContent>>{
-		let mut new_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>> = original_source_cache.clone();
+	/// Checks every dependency's script against the cache, returning the ones that drifted along with any that couldn't be compiled
+	pub async fn check(self: Self, source_cache: &mut SourceCache) -> Resolution<Drift>{
+		let mut drifts: Vec<Drift> = Vec::new();
+		let mut failures: Vec<MpmError> = Vec::new();
 
 		for mpm_dependency in self.dependencies{
-			new_source_cache = mpm_dependency.solve(new_source_cache).await;
+			let dep_name: String = mpm_dependency.name.clone();
+			match mpm_dependency.check(&self.config_path_buf, source_cache).await {
+				Ok(Some(drift)) => drifts.push(drift),
+				Ok(None) => {},
+				Err(e) => failures.push(MpmError::Dependency {
+					myth: self.name.clone(),
+					dependency: dep_name,
+					source: Box::new(e),
+				}),
+			}
 		}
 
-		return new_source_cache;
+		return Resolution {
+			myth: self.name,
+			resolved: drifts,
+			failures,
+		};
 	}
 }
 
-pub fn find_package(package_dir: &Path) -> Option<MPMPackage>{
-	let mut mpm_package_opt: Option<MPMPackage> = None;
+fn warn_deprecated(myth: &str, myth_path: &Path, dependency: &str, source_url: &str, deprecation: &Deprecation) {
+	let reason: String = deprecation.message.as_ref().map(|message| format!(": {}", message)).unwrap_or_default();
+	events::emit(Message::Warning {
+		myth_path: Some(myth_path.to_path_buf()),
+		dependency: Some(dependency.to_string()),
+		message: format!("'{}' is deprecated{}", source_url, reason),
+	});
+	let help: String = deprecation.replacement.as_ref().map(|replacement| format!("\n  = help: replace it with '{}'", replacement)).unwrap_or_default();
+	tracing::warn!(
+		myth,
+		dependency,
+		source_url,
+		reason = deprecation.message,
+		replacement = deprecation.replacement,
+		"{}: dependency '{}' ('{}') is deprecated{}{}", myth, dependency, source_url, reason, help
+	);
+}
 
-	for file_entry in fs::read_dir(package_dir).unwrap() {
-		let file_entry: fs::DirEntry = file_entry.unwrap();
+/// A myth is named after the directory its manifest is in
+fn get_myth_name(config_file_path: &Path) -> Option<String> {
+	let dir_path: &Path = config_file_path.parent()?;
+	// a relative path like "muse-package.toml" has no directory name until it's resolved
+	return fs::canonicalize(dir_path).ok() // Option<PathBuf>
+		.as_deref()
+		.and_then(|p| p.file_name()) // Option<&OsStr>
+		.and_then(|os_str| os_str.to_str()) // Option<&str>
+		.map(|name| name.to_owned());
+}
+
+/// Finds the manifest in `package_dir` without reading it
+pub fn find_manifest_path(package_dir: &Path) -> Result<PathBuf>{
+	let not_found = || MpmError::PackageNotFound {
+		dir: package_dir.to_path_buf(),
+		file_name: FILE_NAME_STRING,
+	};
+	if !package_dir.is_dir() {
+		return Err(not_found());
+	}
+	for file_entry in fs::read_dir(package_dir).map_err(|e| MpmError::io("read", package_dir, e))? {
+		let file_entry: fs::DirEntry = file_entry.map_err(|e| MpmError::io("read", package_dir, e))?;
 		let file_path: PathBuf = file_entry.path();
-		if file_path.file_name().unwrap() == FILE_NAME_STRING {
-			mpm_package_opt = Some(MPMPackage::new(&file_path));
-			return mpm_package_opt;
+		if file_entry.file_name() == FILE_NAME_STRING {
+			return Ok(file_path);
 		}
 	}
-	return mpm_package_opt;
+	return Err(not_found());
 }
 
-/// Searches for files named `file_name` under the given `start_dir` directory and returns a Vec with the paths to the files found.
-pub fn search_for_packages(start_dir: &Path) -> Vec<MPMPackage> {
-	let mut found_configs: Vec<MPMPackage> = Vec::new();
+pub fn find_package(package_dir: &Path, workspace: &Workspace) -> Result<MPMPackage>{
+	return MPMPackage::new(&find_manifest_path(package_dir)?, workspace);
+}
+
+/// Directories never searched for myths wherever they are, the manifests inside them belong to downloaded sources or other tools
+pub const DEFAULT_SEARCH_EXCLUDES: [&str; 3] = [CACHE_DIR_NAME, ".git", "node_modules"];
+
+/// Lists every manifest under `start_dir` without reading them.
+/// `DEFAULT_SEARCH_EXCLUDES`, the workspace's cache and `exclude` globs, and any nested workspace are skipped.
+/// Symlinks are only followed when the workspace says to
+fn find_manifest_paths(start_dir: &Path, workspace: &Workspace) -> Result<Vec<PathBuf>> {
+	let exclude_set: GlobSet = build_glob_set(&workspace.config.exclude)?;
+	let cache_path: PathBuf = workspace.get_cache_path();
+	let is_skipped = |entry: &walkdir::DirEntry| -> bool {
+		if entry.depth() == 0 || !entry.file_type().is_dir() {
+			return false;
+		}
+		let dir_path: &Path = entry.path();
+		if DEFAULT_SEARCH_EXCLUDES.iter().any(|dir_name| entry.file_name() == *dir_name) || dir_path == cache_path {
+			return true;
+		}
+		// a map inside this one installs its own myths
+		if dir_path.join(WORKSPACE_FILE_NAME).is_file() {
+			tracing::debug!(path = %dir_path.display(), "not searching {}, it has its own {}", dir_path.display(), WORKSPACE_FILE_NAME);
+			return true;
+		}
+		let relative_path: &Path = dir_path.strip_prefix(&workspace.root_path).unwrap_or(dir_path);
+		return exclude_set.is_match(to_relative_key(relative_path));
+	};
 
-	for entry in WalkDir::new(start_dir).follow_links(true).into_iter().filter_map(|e| e.ok())
+	let mut manifest_paths: Vec<PathBuf> = Vec::new();
+	let mut visited_paths: HashSet<PathBuf> = HashSet::new();
+	for entry in WalkDir::new(start_dir).follow_links(workspace.config.follow_symlinks).into_iter().filter_entry(|entry| !is_skipped(entry))
 	{
+		let entry: walkdir::DirEntry = match entry {
+			Ok(entry) => entry,
+			// a symlink back up the tree is noticed rather than followed forever
+			Err(e) => {
+				tracing::debug!("skipping {}", e);
+				continue;
+			},
+		};
+		if entry.file_name() != FILE_NAME_STRING {
+			continue;
+		}
+		// the same myth reached through two symlinks is only installed once
 		let path: &Path = entry.path();
-		if path
-			.file_name()
-			.and_then(|n: &std::ffi::OsStr| n.to_str())
-			.map(|n| n == FILE_NAME_STRING)
-			.unwrap_or(false)
-		{
-			found_configs.push(MPMPackage::new(path));
+		let canonical_path: PathBuf = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+		if visited_paths.insert(canonical_path) {
+			manifest_paths.push(path.to_path_buf());
+		}
+	}
+	return Ok(manifest_paths);
+}
+
+/// Reads every manifest before failing, so all of their problems are reported together
+fn read_packages(manifest_paths: &[PathBuf], workspace: &Workspace) -> Result<Vec<MPMPackage>> {
+	let mut found_configs: Vec<MPMPackage> = Vec::new();
+	let mut diagnostics: Vec<Diagnostic> = Vec::new();
+	for manifest_path in manifest_paths {
+		match MPMPackage::new(manifest_path, workspace) {
+			Ok(mpm_package) => found_configs.push(mpm_package),
+			Err(MpmError::Manifest { diagnostics: mut package_diagnostics }) => diagnostics.append(&mut package_diagnostics),
+			Err(e) => return Err(e),
 		}
 	}
 
-	return found_configs;
+	if !diagnostics.is_empty() {
+		return Err(MpmError::Manifest {
+			diagnostics,
+		});
+	}
+	return Ok(found_configs);
+}
+
+/// Finds and reads every myth under `start_dir`, see `find_manifest_paths` for what's skipped
+pub fn search_for_packages(start_dir: &Path, workspace: &Workspace) -> Result<Vec<MPMPackage>> {
+	return read_packages(&find_manifest_paths(start_dir, workspace)?, workspace);
+}
+
+/// Finds the manifests of a myth given as its directory, or by its name anywhere under `start_dir`.
+/// Anything with a path separator in it, or naming an existing directory, is taken as a path
+pub fn find_myth_manifest_paths(myth: &str, start_dir: &Path, workspace: &Workspace) -> Result<Vec<PathBuf>> {
+	let myth_path: &Path = Path::new(myth);
+	if myth_path.is_dir() || myth_path.components().count() > 1 {
+		return Ok(vec![find_manifest_path(myth_path)?]);
+	}
+
+	let named_paths: Vec<(String, PathBuf)> = find_manifest_paths(start_dir, workspace)?
+		.into_iter()
+		.filter_map(|manifest_path| get_myth_name(&manifest_path).map(|name| (name, manifest_path)))
+		.collect();
+	let matching_paths: Vec<PathBuf> = named_paths.iter()
+		.filter(|(name, _)| name == myth)
+		.map(|(_, manifest_path)| manifest_path.clone())
+		.collect();
+	if matching_paths.is_empty() {
+		let name_refs: Vec<&str> = named_paths.iter().map(|(name, _)| name.as_str()).collect();
+		return Err(MpmError::MythNotFound {
+			name: myth.to_string(),
+			dir: start_dir.to_path_buf(),
+			suggestion: suggest(myth, &name_refs).map(|name| name.to_string()),
+		});
+	}
+	return Ok(matching_paths);
+}
+
+/// Reads each myth given to `--myth`, by directory or by name, in the order given and only once each
+pub fn select_packages(myths: &[String], start_dir: &Path, workspace: &Workspace) -> Result<Vec<MPMPackage>> {
+	let mut manifest_paths: Vec<PathBuf> = Vec::new();
+	let mut visited_paths: HashSet<PathBuf> = HashSet::new();
+	for myth in myths {
+		for manifest_path in find_myth_manifest_paths(myth, start_dir, workspace)? {
+			let canonical_path: PathBuf = fs::canonicalize(&manifest_path).unwrap_or(manifest_path.clone());
+			if visited_paths.insert(canonical_path) {
+				manifest_paths.push(manifest_path);
+			}
+		}
+	}
+	return read_packages(&manifest_paths, workspace);
 }
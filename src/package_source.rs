@@ -1,79 +1,400 @@
-use std::io::copy;
-use std::str::FromStr;
 use zip::ZipArchive;
 use semver::{VersionReq, Version};
-use tempfile::tempdir;
-use std::fs::File;
 use std::io;
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use serde::Deserialize;
-use super::csharp_parse::compile_to_single_script;
-use anyhow::{Result, anyhow};
-
-fn unzip_file_to_directory(zip_path: &Path, output_path: &Path){
-	// Open the .zip file
-	// println!("zip_path={}", zip_path.to_str().unwrap());
-	let zip_file = File::open(zip_path).unwrap();
-	let mut archive: ZipArchive<File> = ZipArchive::new(zip_file).unwrap();
-
-	// Iterate through each entry in the .zip archive
-	for i in 0..archive.len() {
-		let mut file = archive.by_index(i).unwrap();
-		let file_path = output_path.join(file.mangled_name());
-
-		// If the file is a directory, create it
-		if file.name().ends_with('/') {
-			std::fs::create_dir_all(&file_path).unwrap();
-		} else {
-			// Ensure the file's parent directory exists
-			if let Some(parent) = file_path.parent() {
-				std::fs::create_dir_all(parent).unwrap();
-			}
+use serde::{Deserialize, Serialize};
+use regex::Regex;
+use super::logging;
+use super::events::{self, Message};
+use super::csharp_parse::{compile_to_single_script, CompileOptions};
+use super::cache::{get_tree_path, load_compiled, load_tree_file, save_compiled, save_tree_file};
+use super::github;
+use super::user_config::{UserConfig, ArchiveLimits};
+use super::manifest::{RawMPMConfig, Deprecation};
+use super::package::FILE_NAME_STRING;
+use super::collect::{collect_scripts, is_ignore_file, select_features, to_relative_key, Feature, ScriptFilter, ScriptSelector};
+use super::error::{MpmError, ArchiveViolation, Result};
+
+/// Downloaded release archives, keyed by `get_cache_key` and then by version
+pub type ReleaseCache = HashMap<PathBuf, HashMap<Version, PackageSourceContent>>;
+
+/// Downloaded releases, along with the trees extracted from them
+#[derive(Debug, Default)]
+pub struct SourceCache {
+	pub releases: ReleaseCache,
+	/// Keyed like `releases`, each tree's files are kept on disk under the cache directory too
+	trees: HashMap<PathBuf, HashMap<Version, ExtractedTree>>,
+	/// The cache directory, compiled scripts and extracted files are kept in it so an unchanged dependency isn't compiled again. `None` compiles every time
+	pub cache_path: Option<PathBuf>,
+	/// Reads what's kept in the cache directory without adding to it, for commands that mustn't write anything
+	pub is_read_only: bool,
+	/// Where releases are downloaded from and how big their archives may be
+	pub user_config: UserConfig,
+}
+
+impl SourceCache {
+	pub fn new(releases: ReleaseCache, cache_path: Option<PathBuf>, user_config: UserConfig) -> Self {
+		return SourceCache {
+			releases,
+			trees: HashMap::new(),
+			cache_path,
+			is_read_only: false,
+			user_config,
+		};
+	}
 
-			// Write the file contents
-			let mut outfile = File::create(&file_path).unwrap();
-			let _ = io::copy(&mut file, &mut outfile);
+	/// The tree extracted from a release, opened again if the archive isn't the one the cached tree came from
+	pub fn get_tree(self: &mut Self, package_source_content: &PackageSourceContent) -> Result<&mut ExtractedTree> {
+		let source_url_string: String = package_source_content.source_url.to_string_lossy().to_string();
+		let archive_hash: blake3::Hash = blake3::hash(&package_source_content.data);
+		let version_trees: &mut HashMap<Version, ExtractedTree> = self.trees
+			.entry(get_cache_key(&package_source_content.source_url, &package_source_content.asset))
+			.or_default();
+		let is_stale: bool = version_trees.get(&package_source_content.version)
+			.map(|extracted_tree| extracted_tree.archive_hash != archive_hash)
+			.unwrap_or(true);
+		if is_stale {
+			tracing::debug!(source_url = source_url_string, version = package_source_content.version.to_string(), "opening {} {}", source_url_string, package_source_content.version);
+			let extracted_tree: ExtractedTree = ExtractedTree::open(
+				&package_source_content.data,
+				archive_hash,
+				&source_url_string,
+				package_source_content.asset.is_some(),
+				self.user_config.archive,
+				self.cache_path.as_deref().map(|cache_path| get_tree_path(cache_path, &archive_hash)),
+				self.is_read_only
+			)?;
+			version_trees.insert(package_source_content.version.clone(), extracted_tree);
 		}
+		return Ok(version_trees.get_mut(&package_source_content.version).expect("the tree was just inserted"));
+	}
+
+	/// Reads the manifest of the package at `inner_path` of a release, if it has one, without extracting the rest of it
+	pub fn read_manifest(self: &mut Self, package_source_content: &PackageSourceContent, inner_path: &str) -> Result<Option<RawMPMConfig>> {
+		let source_url_string: String = package_source_content.source_url.to_string_lossy().to_string();
+		return self.get_tree(package_source_content)?.read_package_manifest(inner_path, &source_url_string);
 	}
 }
 
-fn find_single_subdirectory(path: &Path) -> Result<PathBuf> {
-	let mut directories: Vec<PathBuf> = Vec::new();
+const UNIX_FILE_TYPE_MASK: u32 = 0o170000;
+const UNIX_REGULAR_FILE: u32 = 0o100000;
+const UNIX_DIRECTORY: u32 = 0o040000;
+const UNIX_SYMLINK: u32 = 0o120000;
+
+/// Refuses entries that aren't plain files or directories, going by the unix mode when the zip recorded one
+fn check_entry_type(entry_name: &str, unix_mode: Option<u32>) -> std::result::Result<(), ArchiveViolation> {
+	let file_type: u32 = match unix_mode {
+		Some(mode) => mode & UNIX_FILE_TYPE_MASK,
+		None => return Ok(()),
+	};
+	match file_type {
+		// zips made on windows often leave the type out entirely
+		0 | UNIX_REGULAR_FILE | UNIX_DIRECTORY => return Ok(()),
+		UNIX_SYMLINK => return Err(ArchiveViolation::Symlink {
+			entry: entry_name.to_string(),
+		}),
+		_ => return Err(ArchiveViolation::SpecialFile {
+			entry: entry_name.to_string(),
+		}),
+	}
+}
+
+/// Reads one entry into memory, counting its bytes as they're read since the sizes in the zip's headers can lie
+fn read_entry(archive: &mut ZipArchive<io::Cursor<bytes::Bytes>>, index: usize, source_url: &str, limits: &ArchiveLimits, total_bytes: &mut u64) -> Result<Vec<u8>> {
+	let refuse = |violation: ArchiveViolation| MpmError::UnsafeArchive {
+		source_url: source_url.to_string(),
+		violation,
+	};
+	let mut file = archive.by_index(index).map_err(|e| MpmError::Archive {
+		source_url: source_url.to_string(),
+		message: e.to_string(),
+	})?;
+	let entry_name: String = file.name().to_string();
+	let file_limit: u64 = limits.max_file_bytes.min(limits.max_total_bytes - *total_bytes);
+	let mut buffer: Vec<u8> = Vec::new();
+	io::Read::read_to_end(&mut io::Read::take(&mut file, file_limit + 1), &mut buffer).map_err(|e| MpmError::Archive {
+		source_url: source_url.to_string(),
+		message: format!("couldn't read '{}': {}", entry_name, e),
+	})?;
+	let read_bytes: u64 = buffer.len() as u64;
+	if read_bytes > limits.max_file_bytes {
+		return Err(refuse(ArchiveViolation::FileTooLarge {
+			entry: entry_name,
+			limit: limits.max_file_bytes,
+		}));
+	}
+	if read_bytes > file_limit {
+		return Err(refuse(ArchiveViolation::TooLarge {
+			limit: limits.max_total_bytes,
+		}));
+	}
+	*total_bytes += read_bytes;
+	return Ok(buffer);
+}
+
+/// The folder every entry sits in, if there's exactly one and no file beside it
+fn find_top_folder(file_keys: &[&String], dir_keys: &[&String]) -> Option<String> {
+	let mut top_folder_opt: Option<&str> = None;
+	// a directory entry can be the top folder itself, a file has to be inside it
+	let top_folders = file_keys.iter().map(|file_key| file_key.split_once('/').map(|(top_folder, _)| top_folder))
+		.chain(dir_keys.iter().map(|dir_key| Some(dir_key.split('/').next().unwrap_or(dir_key))));
+	for top_folder in top_folders {
+		let top_folder: &str = top_folder?;
+		match top_folder_opt {
+			Some(existing) if existing != top_folder => return None,
+			_ => top_folder_opt = Some(top_folder),
+		}
+	}
+	return top_folder_opt.map(|top_folder| top_folder.to_string());
+}
+
+/// A package read out of an extracted release
+struct ArchivePackage {
+	/// Its own manifest, problems in it are ignored like with `read_package_manifest`
+	manifest: Option<RawMPMConfig>,
+	/// Keyed by their path relative to the package like `collect_scripts`, or by file name for a single script
+	scripts: HashMap<String, String>,
+}
+
+/// A release archive whose entries were all vetted up front, each file only being decompressed once something reads it.
+/// Dependencies on different paths of one release share it, and with a cache directory the files decompressed are kept for later runs
+#[derive(Debug)]
+pub struct ExtractedTree {
+	/// The archive it was opened from, a cached tree is only used while this still matches
+	pub archive_hash: blake3::Hash,
+	archive: ZipArchive<io::Cursor<bytes::Bytes>>,
+	/// Index of every file in the archive, keyed by its path below the archive's top folder, which zipballs always have and assets only sometimes do
+	file_indices: HashMap<String, usize>,
+	/// Every directory holding files, as its path below the top folder
+	dir_keys: HashSet<String>,
+	/// Where the decompressed files are kept, `None` keeps them in memory only
+	tree_path: Option<PathBuf>,
+	/// Reads the files kept in `tree_path` without adding new ones
+	is_read_only: bool,
+	/// Files read so far by this run
+	files: HashMap<String, Vec<u8>>,
+	limits: ArchiveLimits,
+	/// Uncompressed bytes read out of the archive so far, held against `limits`
+	total_bytes: u64,
+}
+
+impl ExtractedTree {
+	/// Lists the archive's entries, refusing unsafe ones and more files than the user's archive limits allow
+	fn open(data: &bytes::Bytes, archive_hash: blake3::Hash, source_url: &str, is_asset: bool, limits: ArchiveLimits, tree_path: Option<PathBuf>, is_read_only: bool) -> Result<Self> {
+		let archive_error = |e: zip::result::ZipError| MpmError::Archive {
+			source_url: source_url.to_string(),
+			message: e.to_string(),
+		};
+		let refuse = |violation: ArchiveViolation| MpmError::UnsafeArchive {
+			source_url: source_url.to_string(),
+			violation,
+		};
+		let mut archive: ZipArchive<io::Cursor<bytes::Bytes>> = ZipArchive::new(io::Cursor::new(data.clone())).map_err(archive_error)?;
+		if archive.len() > limits.max_files {
+			return Err(refuse(ArchiveViolation::TooManyFiles {
+				limit: limits.max_files,
+			}));
+		}
+
+		// every entry is vetted from its header before anything is decompressed
+		let mut dir_entry_keys: Vec<String> = Vec::new();
+		let mut entry_indices: Vec<(String, usize)> = Vec::new();
+		for index in 0..archive.len() {
+			let file = archive.by_index_raw(index).map_err(archive_error)?;
+			check_entry_type(file.name(), file.unix_mode()).map_err(refuse)?;
+			let entry_key: String = file.enclosed_name()
+				.map(to_relative_key)
+				.ok_or_else(|| refuse(ArchiveViolation::UnsafePath {
+					entry: file.name().to_string(),
+				}))?;
+			match file.is_dir() {
+				true => dir_entry_keys.push(entry_key),
+				false => entry_indices.push((entry_key, index)),
+			}
+		}
 
-	// Iterate over the entries in the directory.
-	let entries = fs::read_dir(path)?
-		.filter_map(Result::ok) // Filter out Err results and unwrap Ok values.
-		.filter(|entry| entry.path().is_dir()); // Consider only directories.
+		let file_keys: Vec<&String> = entry_indices.iter().map(|(file_key, _)| file_key).collect();
+		let top_folder_opt: Option<String> = find_top_folder(&file_keys, &dir_entry_keys.iter().collect::<Vec<&String>>());
+		if top_folder_opt.is_none() && !is_asset {
+			return Err(MpmError::Archive {
+				source_url: source_url.to_string(),
+				message: String::from("expected the zipball to hold a single folder"),
+			});
+		}
+		let prefix: String = top_folder_opt.map(|top_folder| format!("{}/", top_folder)).unwrap_or_default();
+		let strip_top_folder = |entry_key: &str| -> String { entry_key.strip_prefix(&prefix).unwrap_or(entry_key).to_string() };
 
-	// Collect directories
-	for entry in entries {
-		directories.push(entry.path());
-		if directories.len() > 1 {
-			// If more than one directory is found, return an error.
-			return Err(anyhow!("More than one subdirectory found in {}", path.display()));
+		let mut dir_keys: HashSet<String> = HashSet::new();
+		for (file_key, _) in &entry_indices {
+			let mut dir_key: String = strip_top_folder(file_key);
+			while let Some((parent_key, _)) = dir_key.rsplit_once('/') {
+				dir_key = parent_key.to_string();
+				dir_keys.insert(dir_key.clone());
+			}
+		}
+		// empty directories still exist, the top folder's own entry doesn't count
+		for dir_entry_key in dir_entry_keys.iter().filter(|dir_entry_key| format!("{}/", dir_entry_key) != prefix) {
+			dir_keys.insert(strip_top_folder(dir_entry_key));
 		}
+		let file_indices: HashMap<String, usize> = entry_indices.iter()
+			.map(|(entry_key, index)| (strip_top_folder(entry_key), *index))
+			.collect();
+		return Ok(ExtractedTree {
+			archive_hash,
+			archive,
+			file_indices,
+			dir_keys,
+			tree_path,
+			is_read_only,
+			files: HashMap::new(),
+			limits,
+			total_bytes: 0,
+		});
 	}
 
-	// Check how many directories were found and act accordingly.
-	match directories.len() {
-		1 => Ok(directories[0].clone()), // Return the first (and only) directory.
-		0 => Err(anyhow!("No subdirectories found in {}", path.display())),
-		_ => unreachable!(), // This case is already handled above, so it's unreachable.
+	/// A file's contents, from what earlier runs kept on disk or else decompressed out of the archive
+	fn read_file(self: &mut Self, file_key: &str, source_url: &str) -> Result<&[u8]> {
+		if !self.files.contains_key(file_key) {
+			let index: usize = match self.file_indices.get(file_key) {
+				Some(index) => *index,
+				None => return Ok(&[]),
+			};
+			let kept_opt: Option<Vec<u8>> = self.tree_path.as_deref().and_then(|tree_path| load_tree_file(tree_path, file_key));
+			let contents: Vec<u8> = match kept_opt {
+				Some(contents) => contents,
+				None => {
+					let contents: Vec<u8> = read_entry(&mut self.archive, index, source_url, &self.limits, &mut self.total_bytes)?;
+					if let Some(tree_path) = self.tree_path.as_deref().filter(|_| !self.is_read_only) {
+						save_tree_file(tree_path, file_key, &contents)?;
+					}
+					contents
+				},
+			};
+			self.files.insert(file_key.to_string(), contents);
+		}
+		return Ok(&self.files[file_key]);
+	}
+
+	fn read_text(self: &mut Self, file_key: &str, source_url: &str) -> Result<String> {
+		let contents: &[u8] = self.read_file(file_key, source_url)?;
+		return String::from_utf8(contents.to_vec()).map_err(|_| MpmError::Archive {
+			source_url: source_url.to_string(),
+			message: format!("'{}' isn't UTF-8 text", file_key),
+		});
+	}
+
+	fn read_manifest_at(self: &mut Self, manifest_key: &str, source_url: &str) -> Result<Option<RawMPMConfig>> {
+		if !self.file_indices.contains_key(manifest_key) {
+			return Ok(None);
+		}
+		let contents: String = self.read_text(manifest_key, source_url)?;
+		let (raw_config, _): (RawMPMConfig, _) = RawMPMConfig::parse(Path::new(manifest_key), &contents);
+		return Ok(Some(raw_config));
+	}
+
+	/// Where the manifest of the package at `base_key` would be, beside it for a single script
+	fn get_manifest_key(self: &Self, base_key: &str) -> String {
+		if self.file_indices.contains_key(base_key) {
+			return match base_key.rsplit_once('/') {
+				Some((dir_key, _)) => format!("{}/{}", dir_key, FILE_NAME_STRING),
+				None => FILE_NAME_STRING.to_string(),
+			};
+		}
+		return match base_key.is_empty() {
+			true => FILE_NAME_STRING.to_string(),
+			false => format!("{}/{}", base_key, FILE_NAME_STRING),
+		};
+	}
+
+	/// Reads only the manifest of the package at `inner_path`, if it has one
+	fn read_package_manifest(self: &mut Self, inner_path: &str, source_url: &str) -> Result<Option<RawMPMConfig>> {
+		let manifest_key: String = self.get_manifest_key(inner_path.trim_matches('/'));
+		return self.read_manifest_at(&manifest_key, source_url);
+	}
+
+	/// Reads the package at `inner_path`, either a single script or the files its manifest's globs select, `None` meaning nothing is there
+	fn read_package(self: &mut Self, inner_path: &str, source_url: &str) -> Result<Option<ArchivePackage>> {
+		let base_key: String = inner_path.trim_matches('/').to_string();
+		let manifest_key: String = self.get_manifest_key(&base_key);
+
+		// a single script, with the manifest beside it
+		if self.file_indices.contains_key(&base_key) {
+			let file_name: &str = base_key.rsplit_once('/').map(|(_, file_name)| file_name).unwrap_or(&base_key);
+			let mut scripts: HashMap<String, String> = HashMap::new();
+			scripts.insert(file_name.to_string(), self.read_text(&base_key, source_url)?);
+			return Ok(Some(ArchivePackage {
+				manifest: self.read_manifest_at(&manifest_key, source_url)?,
+				scripts,
+			}));
+		}
+
+		if !base_key.is_empty() && !self.dir_keys.contains(&base_key) {
+			return Ok(None);
+		}
+		let prefix: String = match base_key.is_empty() {
+			true => String::new(),
+			false => format!("{}/", base_key),
+		};
+		let package_keys: Vec<(String, String)> = self.file_indices.keys()
+			.filter_map(|file_key| file_key.strip_prefix(&prefix).map(|relative_key| (relative_key.to_string(), file_key.clone())))
+			.collect();
+
+		let manifest: Option<RawMPMConfig> = self.read_manifest_at(&manifest_key, source_url)?;
+		let mut ignore_files: Vec<(String, String)> = Vec::new();
+		for (relative_key, file_key) in &package_keys {
+			if is_ignore_file(relative_key) {
+				ignore_files.push((relative_key.clone(), self.read_text(file_key, source_url)?));
+			}
+		}
+
+		let filter: ScriptFilter = manifest.as_ref().map(|raw_config| raw_config.filter.clone()).unwrap_or_default();
+		let selector: ScriptSelector = ScriptSelector::new(&filter, &ignore_files)?;
+		let mut scripts: HashMap<String, String> = HashMap::new();
+		for (relative_key, file_key) in package_keys {
+			if selector.is_selected(&relative_key) {
+				let contents: String = self.read_text(&file_key, source_url)?;
+				scripts.insert(relative_key, contents);
+			}
+		}
+		return Ok(Some(ArchivePackage {
+			manifest,
+			scripts,
+		}));
 	}
 }
 
-fn get_psc_from_cache(
-	source_url: PathBuf,
+/// Key a source's downloads are cached under, a release asset gets its own entry next to the zipball of the same repo
+pub fn get_cache_key(source_url: &Path, asset: &Option<String>) -> PathBuf {
+	match asset {
+		Some(asset_name) => PathBuf::from(format!("{}#{}", source_url.to_string_lossy(), asset_name)),
+		None => source_url.to_path_buf(),
+	}
+}
+
+/// Reverses `get_cache_key`, returning the source url and asset name
+pub fn split_cache_key(cache_key: &Path) -> (PathBuf, Option<String>) {
+	let key_string: String = cache_key.to_string_lossy().to_string();
+	match key_string.split_once('#') {
+		Some((source_url, asset_name)) => (PathBuf::from(source_url), Some(asset_name.to_string())),
+		None => (cache_key.to_path_buf(), None),
+	}
+}
+
+/// Finds the newest cached release of `cache_key` matching `version_req`
+pub fn get_psc_from_cache(
+	cache_key: PathBuf,
 	version_req: VersionReq,
-	source_cache: &HashMap<PathBuf, HashMap<Version, PackageSourceContent>>
+	source_cache: &SourceCache
 ) -> Option<PackageSourceContent>{
 	let mut package_source_content_opt: Option<PackageSourceContent> = None;
 	
-	if source_cache.contains_key(&source_url){
-		for (version, psc) in source_cache.get(&source_url).unwrap() {
-			if version_req.matches(&version){
+	if let Some(version_cache) = source_cache.releases.get(&cache_key){
+		// prefer the newest cached release so resolution doesn't depend on map order
+		for (version, psc) in version_cache {
+			let is_newer: bool = package_source_content_opt.as_ref().map(|best| version > &best.version).unwrap_or(true);
+			if version_req.matches(version) && is_newer {
 				package_source_content_opt = Some(psc.clone());
 			}
 		}
@@ -82,26 +403,120 @@ fn get_psc_from_cache(
 	return package_source_content_opt;
 }
 
-fn save_psc_into_cache(original_package_source_content: PackageSourceContent, original_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>>) -> HashMap<PathBuf, HashMap<Version, PackageSourceContent>>{
-	let mut source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>> = original_source_cache.clone();
-	let package_source_content: PackageSourceContent = original_package_source_content.clone();
+fn save_psc_into_cache(package_source_content: PackageSourceContent, source_cache: &mut SourceCache){
+	let path: PathBuf = get_cache_key(&package_source_content.source_url, &package_source_content.asset);
 	
-	if source_cache.contains_key(&package_source_content.source_url) == false{
-		source_cache.insert(package_source_content.source_url, HashMap::new());
-	}
-
-	let path: PathBuf = original_package_source_content.source_url.clone();
-
-	let mut version_cache: HashMap<Version, PackageSourceContent> = source_cache.get(&path).unwrap().to_owned();
-	version_cache.insert(package_source_content.version, original_package_source_content);
-	source_cache.insert(path, version_cache);
-	return source_cache
+	source_cache.releases.entry(path)
+		.or_default()
+		.insert(package_source_content.version.clone(), package_source_content);
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum SourceType {
 	Unknown,
 	GitHubRelease,
+	/// A package directory on disk, compiled in place rather than downloaded
+	Local,
+}
+
+/// A dependency's single script, along with what its own manifest says about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompiledSource {
+	pub script: String,
+	/// `None` for path dependencies, which aren't released
+	pub version: Option<Version>,
+	pub deprecation: Option<Deprecation>,
+}
+
+/// What a compiled script's header says it was built from
+#[derive(Debug, Clone)]
+pub struct ScriptSource {
+	/// The dependency URL, pinned to the release that was compiled
+	pub url: String,
+	pub asset: Option<String>,
+	/// Scripts compiled before the header held the release and path only know the repo
+	pub is_pinned: bool,
+}
+
+/// Header written at the top of every compiled dependency, `parse_source_header` reads it back
+pub fn format_source_header(source_url: &str, version: &Version, inner_path: &str, asset: &Option<String>) -> String {
+	let mut url: String = format!("{}/releases/tag/v{}", source_url, version);
+	if !inner_path.is_empty() {
+		url = format!("{}/{}", url, inner_path);
+	}
+	let source_description: String = match asset {
+		Some(asset_name) => format!("'{}' asset '{}'", url, asset_name),
+		None => format!("'{}'", url),
+	};
+	return format!("DO NOT EDIT!\n// downloaded from {} and compiled into single script using 'github.com/nightcycle/muse-package-manager'", source_description);
+}
+
+/// Finds the header `format_source_header` wrote in a compiled script
+pub fn parse_source_header(script: &str) -> Option<ScriptSource> {
+	let header_pattern: Regex = Regex::new(r"^// downloaded from '([^']+)'(?: asset '([^']+)')?").ok()?;
+	// the header is always near the top, no need to look through the whole script
+	for line in script.lines().take(5) {
+		if let Some(captures) = header_pattern.captures(line) {
+			let url: String = captures.get(1)?.as_str().to_string();
+			return Some(ScriptSource {
+				is_pinned: url.contains("/releases/tag/"),
+				url,
+				asset: captures.get(2).map(|asset| asset.as_str().to_string()),
+			});
+		}
+	}
+	return None;
+}
+
+/// Reads the manifest sitting next to a package's scripts, `target_package_path` being its directory or single script.
+/// Problems in it are ignored, since they're for whoever published the package to fix
+pub fn read_package_manifest(target_package_path: &Path) -> Result<Option<RawMPMConfig>> {
+	let manifest_path: PathBuf = match target_package_path.is_file() {
+		true => target_package_path.with_file_name(FILE_NAME_STRING),
+		false => target_package_path.join(FILE_NAME_STRING),
+	};
+	if !manifest_path.is_file() {
+		return Ok(None);
+	}
+	let contents: String = fs::read_to_string(&manifest_path).map_err(|e| MpmError::io("read", &manifest_path, e))?;
+	let (raw_config, _): (RawMPMConfig, _) = RawMPMConfig::parse(&manifest_path, &contents);
+	return Ok(Some(raw_config));
+}
+
+/// The files making up a package before they're compiled
+struct PackageScripts {
+	scripts: HashMap<String, String>,
+	deprecation: Option<Deprecation>,
+	features: BTreeMap<String, Feature>,
+}
+
+/// Reads the scripts of a package, either a single file or the files under a directory its manifest's globs select, along with its deprecation.
+/// Returns `None` when there's nothing at `target_package_path`
+fn collect_package_scripts(target_package_path: &Path) -> Result<Option<PackageScripts>> {
+	if !target_package_path.is_file() && !target_package_path.is_dir() {
+		return Ok(None);
+	}
+	let raw_config_opt: Option<RawMPMConfig> = read_package_manifest(target_package_path)?;
+	let filter: ScriptFilter = raw_config_opt.as_ref().map(|raw_config| raw_config.filter.clone()).unwrap_or_default();
+	let (deprecation, features): (Option<Deprecation>, BTreeMap<String, Feature>) = match raw_config_opt {
+		Some(raw_config) => (raw_config.deprecated, raw_config.features),
+		None => (None, BTreeMap::new()),
+	};
+
+	let mut scripts: HashMap<String, String> = HashMap::new();
+	if target_package_path.is_file(){
+		if let Some(name) = target_package_path.file_name().and_then(|n| n.to_str()) {
+			let contents: String = fs::read_to_string(target_package_path).map_err(|e| MpmError::io("read", target_package_path, e))?;
+			scripts.insert(name.to_owned(), contents);
+		}
+	}else{
+		scripts = collect_scripts(target_package_path, &filter, &[])?;
+	}
+	return Ok(Some(PackageScripts {
+		scripts,
+		deprecation,
+		features,
+	}));
 }
 
 #[derive(Debug, Clone)]
@@ -109,136 +524,227 @@ pub struct PackageSourceContent{
 	pub data: bytes::Bytes,
 	pub version: Version,
 	pub source_url: PathBuf,
+	pub asset: Option<String>,
+}
+
+/// Lists the releases of a repo in the order GitHub returns them, newest first, as their tag and version.
+/// Tags that aren't versions are skipped with a warning.
+async fn list_releases(
+	client: &octocrab::Octocrab,
+	location: &github::RepoLocation,
+	source_url_str: &str,
+) -> Result<Vec<(String, Version)>> {
+	let repos = &client.repos(&location.owner, &location.repo);
+	let page: octocrab::Page<octocrab::models::repos::Release> = github::send_with_rate_limit(client, location, source_url_str, || async move { repos
+		.releases()
+		.list()
+		// Optional Parameters
+		.per_page(100)
+		// .page(5u32)
+		// Send the request
+		.send()
+		.await
+	}).await?;
+
+	let mut releases: Vec<(String, Version)> = Vec::new();
+	for release in page.items{
+		match Version::parse(&release.tag_name.replace("v", "")) {
+			Ok(release_version) => releases.push((release.tag_name, release_version)),
+			Err(e) => {
+				tracing::warn!(source_url = source_url_str, tag = release.tag_name, "skipping release with tag '{}', it isn't a version: {}", release.tag_name, e);
+			}
+		}
+	}
+	return Ok(releases);
+}
+
+/// The release an install uses out of a listing from `list_releases`, the first one that matches `version_req`
+pub fn select_release<'a>(releases: &'a [(String, Version)], version_req: &VersionReq) -> Option<&'a (String, Version)> {
+	return releases.iter().find(|(_, release_version)| version_req.matches(release_version));
+}
+
+/// Picks the release an install would use, returning its tag and version
+async fn pick_release(
+	client: &octocrab::Octocrab,
+	location: &github::RepoLocation,
+	source_url_str: &str,
+	version_req: &VersionReq,
+) -> Result<(String, Version)> {
+	let releases: Vec<(String, Version)> = list_releases(client, location, source_url_str).await?;
+	return select_release(&releases, version_req)
+		.cloned()
+		.ok_or_else(|| MpmError::NoMatchingRelease {
+			source_url: source_url_str.to_string(),
+			version_req: version_req.to_string(),
+		});
+}
+
+/// Looks up the tag and version of the release of `source_url` an install would pick for `version_req`
+pub async fn find_release(source_url: &str, version_req: &VersionReq, user_config: &UserConfig) -> Result<(String, Version)> {
+	let location: github::RepoLocation = github::locate_repo(source_url, user_config)?;
+	let client: octocrab::Octocrab = github::build_client(&location)?;
+	return pick_release(&client, &location, source_url, version_req).await;
+}
+
+/// Every release of `source_url`, newest first, as their tag and version
+pub async fn fetch_releases(source_url: &str, user_config: &UserConfig) -> Result<Vec<(String, Version)>> {
+	let location: github::RepoLocation = github::locate_repo(source_url, user_config)?;
+	let client: octocrab::Octocrab = github::build_client(&location)?;
+	return list_releases(&client, &location, source_url).await;
 }
 
 impl PackageSourceContent {
+	/// Downloads the newest release matching `version_req`, `dependency_name` of the myth at `myth_path` being who asked for it
 	pub async fn new(
 		source_url: PathBuf,
 		version_req: VersionReq,
 		source_type: SourceType,
-	) -> Self{
-		let source_url_str: &str = source_url.to_str().unwrap();
-		println!("downloading {}", &source_url_str);
-		assert!(source_type == SourceType::GitHubRelease, "not a supported source");
-
-		// let info_start: usize = self.source_url.find("").expect("URL does not contain 'https://github.com/'");
-		let info_string: &str = &source_url_str["https://github.com/".len()..];
-		let mid_slash_start: usize = info_string.find("/").expect("bad github url");
-
-		let owner: &str = &info_string[..mid_slash_start];
-		let repo: &str = &info_string[(mid_slash_start+1)..];
-
-		let client: std::sync::Arc<octocrab::Octocrab> = octocrab::instance();
-
-		let repos = client.repos(owner, repo);
-		let page: octocrab::Page<octocrab::models::repos::Release> = repos
-			.releases()
-			.list()
-			// Optional Parameters
-			.per_page(100)
-			// .page(5u32)
-			// Send the request
-			.send()
-			.await.unwrap();
-
-		let mut release_tag_option: Option<String> = None;
-		let mut release_version_option: Option<Version> = None;
-
-		for release in page.items{
-			match Version::parse(&release.tag_name.replace("v", "")) {
-				Ok(release_version) => {
-					if version_req.matches(&release_version){
-						release_tag_option = Some(release.tag_name.clone()); // Set release_tag here
-						release_version_option = Some(release_version);
-						break;
-					}
-				}
-				Err(e) => {
-					eprintln!("Failed to parse release with tag '{}': {}", release.tag_name, e);
-				}
-			}
+		asset: Option<String>,
+		dependency_name: &str,
+		myth_path: &Path,
+		user_config: &UserConfig,
+	) -> Result<Self>{
+		let source_url_string: String = source_url.to_string_lossy().to_string();
+		let source_url_str: &str = source_url_string.as_str();
+		tracing::info!(source_url = source_url_str, "downloading {}", source_url_str);
+		if source_type != SourceType::GitHubRelease {
+			return Err(MpmError::InvalidUrl {
+				url: source_url_string.clone(),
+				reason: String::from("not a supported source"),
+			});
 		}
-		
 
-		let release_tag: String = release_tag_option.expect("no compatible release found");
+		let location: github::RepoLocation = github::locate_repo(source_url_str, user_config)?;
+
+		let client: octocrab::Octocrab = github::build_client(&location)?;
 
-		let release: octocrab::models::repos::Release = repos.releases()
+		let repos = &client.repos(&location.owner, &location.repo);
+		let (release_tag, version): (String, Version) = pick_release(&client, &location, source_url_str, &version_req).await?;
+
+		let release: octocrab::models::repos::Release = github::send_with_rate_limit(&client, &location, source_url_str, || async { repos.releases()
 			.get_by_tag(&release_tag)
-			.await.unwrap();
+			.await
+		}).await?;
 
-		let zip_url: reqwest::Url = release.zipball_url.expect("bad zip url");
-		// println!("zip_url={:#?}", zip_url.to_string());
+		let mut request: reqwest::RequestBuilder = match &asset {
+			Some(asset_name) => {
+				let release_asset: &octocrab::models::repos::Asset = release.assets.iter()
+					.find(|release_asset| &release_asset.name == asset_name)
+					.ok_or_else(|| MpmError::AssetNotFound {
+						source_url: source_url_string.clone(),
+						tag: release_tag.clone(),
+						asset: asset_name.clone(),
+						available: release.assets.iter().map(|release_asset| release_asset.name.clone()).collect(),
+					})?;
+				// the API url also works for private repos, as long as the raw bytes are asked for
+				github::download_request(release_asset.url.as_str(), &location)
+					.header("Accept", "application/octet-stream")
+			},
+			None => {
+				let zip_url: reqwest::Url = release.zipball_url.clone().ok_or_else(|| MpmError::MissingZipball {
+					source_url: source_url_string.clone(),
+					tag: release_tag.clone(),
+				})?;
+				// println!("zip_url={:#?}", zip_url.to_string());
+				github::download_request(zip_url.as_str(), &location)
+			},
+		};
+		request = request.header("X-GitHub-Api-Version", "2022-11-28");
 
 		// Download the asset
-		let client = reqwest::Client::new();
-		let response: reqwest::Response = match client.get(zip_url.to_string())
-			.header("User-Agent", "request").send().await {
-				Ok(res) => res,
-				Err(e) => panic!("{}", e),
-			};
+		let download_error = |e: reqwest::Error| MpmError::Download {
+			url: e.url().map(|url| url.to_string()).unwrap_or(source_url_string.clone()),
+			message: e.to_string(),
+		};
+		let response: reqwest::Response = github::check_download_response(
+			request.send().await.map_err(download_error)?,
+			&location,
+			source_url_str
+		)?;
 
-		let data: bytes::Bytes = response.bytes().await.unwrap();
+		let mut response: reqwest::Response = response;
+		let total_bytes_opt: Option<u64> = response.content_length();
+		events::emit(Message::DownloadStarted {
+			myth_path: myth_path.to_path_buf(),
+			dependency: dependency_name.to_string(),
+			source_url: source_url_string.clone(),
+			asset: asset.clone(),
+			version: version.clone(),
+			total_bytes: total_bytes_opt,
+		});
+		let progress_bar: indicatif::ProgressBar = logging::download_bar(total_bytes_opt, &format!("{} {}", source_url_str, release_tag));
+		let mut buffer: Vec<u8> = Vec::with_capacity(total_bytes_opt.unwrap_or_default() as usize);
+		while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+			progress_bar.inc(chunk.len() as u64);
+			buffer.extend_from_slice(&chunk);
+		}
+		progress_bar.finish_and_clear();
+		tracing::debug!(source_url = source_url_str, version = version.to_string(), bytes = buffer.len(), "downloaded {} bytes of {} {}", buffer.len(), source_url_str, release_tag);
+		events::emit(Message::DownloadFinished {
+			myth_path: myth_path.to_path_buf(),
+			dependency: dependency_name.to_string(),
+			source_url: source_url_string.clone(),
+			asset: asset.clone(),
+			version: version.clone(),
+			bytes: buffer.len() as u64,
+		});
+		let data: bytes::Bytes = bytes::Bytes::from(buffer);
 
-		let version: Version = release_version_option.unwrap();
-		return PackageSourceContent{
+		return Ok(PackageSourceContent{
 			data,
 			version,
-			source_url
-		};
+			source_url,
+			asset
+		});
 	}
 
-	pub fn compile(self: Self, target_namespace_name: String, inner_path: String) -> String{
-		println!("compiling {}", target_namespace_name);
-		// Create a temporary directory
-		let dir: tempfile::TempDir = tempdir().unwrap();
-		let dir_path: &Path = dir.path();
-		let file_path: std::path::PathBuf = dir_path.join("source.zip");
-
-		let mut file: File = File::create(file_path.clone()).unwrap();
-		let mut content: io::Cursor<bytes::Bytes> =  std::io::Cursor::new(self.data);
-		copy(&mut content, &mut file).unwrap();
-
-		let unzip_dir_path: std::path::PathBuf = dir_path.join("unzipped_directory");
-		unzip_file_to_directory(file_path.as_path(), unzip_dir_path.as_path());
-
-		let inner_dir_path: PathBuf = find_single_subdirectory(&unzip_dir_path).unwrap();
-		let target_package_path: PathBuf = inner_dir_path.join(inner_path);
-
-		// let source_namespace: String = target_package_path.file_stem().unwrap().to_str().unwrap().to_string();
-		let tpdp_clone = target_package_path.clone();
-		let target_path_debug_str: &str = tpdp_clone.to_str().unwrap();
-		let mut scripts: HashMap<String, String> = HashMap::new();
-
-
-		if target_package_path.is_file(){
-			if let Some(name) = target_package_path.file_name().and_then(|n| n.to_str()) {
-				let contents: String = fs::read_to_string(&target_package_path).unwrap();
-				scripts.insert(name.to_owned(), contents);
-			}
-		}else if target_package_path.is_dir() {
-			for entry in fs::read_dir(target_package_path).expect(&format!("can't read directory '{}'", target_path_debug_str)) {
-				let entry = entry.unwrap();
-				let path = entry.path();
-				
-				// Ensure the entry is a file
-				if path.is_file() {
-					// Get the file name as a String
-					if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-						// Read the file's contents into a String
-						let contents = fs::read_to_string(&path).unwrap();
-						// Insert the file name and contents into the map
-						scripts.insert(name.to_owned(), contents);
-					}
-				}
-			}
+	/// Hash of everything the script compiled from `inner_path` of this release depends on, including the version of mpm compiling it
+	pub fn get_compiled_key(self: &Self, inner_path: &str, namespace_name: &str, compile_options: &CompileOptions, features: &[String]) -> String {
+		let mut hasher: blake3::Hasher = blake3::Hasher::new();
+		let parts: [String; 8] = [
+			self.source_url.to_string_lossy().to_string(),
+			self.asset.clone().unwrap_or_default(),
+			self.version.to_string(),
+			// a release whose archive was replaced compiles to something else
+			blake3::hash(&self.data).to_hex().to_string(),
+			inner_path.to_string(),
+			namespace_name.to_string(),
+			compile_options.fingerprint(),
+			features.join(","),
+		];
+		for part in parts.iter().map(|part| part.as_str()).chain([env!("CARGO_PKG_VERSION")]) {
+			hasher.update(part.as_bytes());
+			hasher.update(&[0]);
 		}
+		return hasher.finalize().to_hex().to_string();
+	}
 
-		
-		return compile_to_single_script(
-			format!("DO NOT EDIT!\n// downloaded from '{}' and compiled into single script using 'github.com/nightcycle/muse-package-manager'", self.source_url.to_str().unwrap()),
+	/// Compiles the package at `inner_path` out of this release's `extracted_tree`, with the files and symbols of its selected `features`
+	pub fn compile(self: Self, extracted_tree: &mut ExtractedTree, dependency_name: &str, target_namespace_name: String, inner_path: String, compile_options: &CompileOptions, features: &[String]) -> Result<CompiledSource>{
+		tracing::info!(dependency = dependency_name, version = self.version.to_string(), "compiling {}", dependency_name);
+		let source_url_string: String = self.source_url.to_string_lossy().to_string();
+		let ArchivePackage { manifest, mut scripts } = extracted_tree.read_package(&inner_path, &source_url_string)?
+			.ok_or_else(|| MpmError::InnerPathNotFound {
+				source_url: source_url_string.clone(),
+				inner_path: inner_path.clone(),
+			})?;
+		let (deprecation, package_features): (Option<Deprecation>, BTreeMap<String, Feature>) = match manifest {
+			Some(raw_config) => (raw_config.deprecated, raw_config.features),
+			None => (None, BTreeMap::new()),
+		};
+		let defines: Vec<String> = select_features(&mut scripts, &package_features, features, &source_url_string)?;
+
+		let script: String = compile_to_single_script(
+			format_source_header(&source_url_string, &self.version, &inner_path, &self.asset),
 			target_namespace_name, 
-			scripts
+			scripts,
+			&compile_options.clone().with_defines(defines)
 		);
+		return Ok(CompiledSource {
+			script,
+			version: Some(self.version),
+			deprecation,
+		});
 	}
 }
 
@@ -247,61 +753,273 @@ pub struct PackageSource {
 	pub source_url: PathBuf,
 	pub version_req: VersionReq,
 	pub source_type: SourceType,
-	pub inner_path: String
+	pub inner_path: String,
+	pub asset: Option<String>,
+	/// Optional parts of the package to compile in, by the names in its `[features]`
+	#[serde(default)]
+	pub features: Vec<String>,
 }
 
 impl PackageSource {
-	pub fn new(value: String) -> Self {
-		let tag_start: usize = value.find("/tag/").expect("URL does not contain '/tag/'");
+	pub fn new(value: String, asset: Option<String>) -> Result<Self> {
+		let invalid_url = |reason: &str| MpmError::InvalidUrl {
+			url: value.clone(),
+			reason: reason.to_string(),
+		};
+		let tag_start: usize = value.find("/tag/").ok_or_else(|| invalid_url("it doesn't contain '/tag/'"))?;
 		let version_start: usize = tag_start + "/tag/".len();
-		let version_end: usize = value[version_start..].find('/').expect("Could not find the end of the version segment") + version_start;
+		// an asset can be used as a whole, so the inner path after the version is optional for them
+		let version_end: usize = match value[version_start..].find('/') {
+			Some(slash_start) => slash_start + version_start,
+			None if asset.is_some() => value.len(),
+			None => return Err(invalid_url("there's no path after the version tag")),
+		};
 		
 		let version_string: String = value[version_start..version_end].to_string().to_lowercase().replace("v", "");
-		let inner_path: String = value[version_end + 1..].to_string();
+		let inner_path: String = value.get(version_end + 1..).unwrap_or("").to_string();
 
-		let releases_start: usize = value.find("/releases/").expect("URL does not contain '/releases/'");
+		let releases_start: usize = value.find("/releases/").ok_or_else(|| invalid_url("it doesn't contain '/releases/'"))?;
 
-		let source_url: PathBuf = PathBuf::from_str(&value[..releases_start].to_string()).unwrap();
+		let source_url: PathBuf = PathBuf::from(&value[..releases_start]);
 
-		let version_req: VersionReq = VersionReq::parse(version_string.as_str()).expect("bad version req");
+		let version_req: VersionReq = VersionReq::parse(version_string.as_str()).map_err(|e| MpmError::InvalidVersionReq {
+			url: value.clone(),
+			requirement: value[version_start..version_end].to_string(),
+			source: e,
+		})?;
 		
 		let source_type: SourceType = SourceType::GitHubRelease;
 
-		return PackageSource{
+		return Ok(PackageSource{
 			source_url,
 			version_req,
 			source_type,
-			inner_path
+			inner_path,
+			asset,
+			features: Vec::new(),
+		});
+	}
+
+	/// A path dependency, `path` being the package's directory or single script relative to `manifest_dir_path`.
+	/// `inner_path` keeps the path as written so the generated header doesn't depend on where mpm was run from
+	pub fn new_local(manifest_dir_path: &Path, path: String) -> Self {
+		return PackageSource {
+			source_url: manifest_dir_path.join(&path),
+			version_req: VersionReq::STAR,
+			source_type: SourceType::Local,
+			inner_path: path,
+			asset: None,
+			features: Vec::new(),
 		};
 	}
 
+	fn compile_local(self: Self, dependency_name: &str, namespace_name: String, compile_options: &CompileOptions) -> Result<CompiledSource>{
+		tracing::info!(dependency = dependency_name, "compiling {}", dependency_name);
+		let PackageScripts { mut scripts, deprecation, features } = collect_package_scripts(&self.source_url)?
+			.ok_or_else(|| MpmError::PathDependencyNotFound {
+				path: self.source_url.clone(),
+			})?;
+		let defines: Vec<String> = select_features(&mut scripts, &features, &self.features, &self.inner_path)?;
+		let script: String = compile_to_single_script(
+			format!("DO NOT EDIT!\n// compiled from local path '{}' into single script using 'github.com/nightcycle/muse-package-manager'", self.inner_path),
+			namespace_name,
+			scripts,
+			&compile_options.clone().with_defines(defines)
+		);
+		return Ok(CompiledSource {
+			script,
+			version: None,
+			deprecation,
+		});
+	}
+
+	/// Compiles the package into `namespace_name`, downloading its release unless the cache has it.
+	/// `myth_path` is the manifest of the myth depending on it, for the download messages
 	pub async fn solve(
 		self: Self, 
+		dependency_name: &str,
+		myth_path: &Path,
 		namespace_name: String,
-		source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>>
-	) -> (HashMap<PathBuf, HashMap<Version, PackageSourceContent>>, String){
+		compile_options: &CompileOptions,
+		source_cache: &mut SourceCache,
+		offline: bool
+	) -> Result<CompiledSource>{
+		if self.source_type == SourceType::Local {
+			return self.compile_local(dependency_name, namespace_name, compile_options);
+		}
 
 		let content_option: Option<PackageSourceContent> = get_psc_from_cache(
-			self.source_url.clone(),
+			get_cache_key(&self.source_url, &self.asset),
 			self.version_req.clone(),
-			&source_cache
+			source_cache
 		);
 		
-		if content_option.is_some(){		
-			let package_source_content: PackageSourceContent = content_option.unwrap();
-			return (source_cache, package_source_content.compile(namespace_name, self.inner_path));
-		}else{
-			let package_source_content: PackageSourceContent = PackageSourceContent::new(
-				self.source_url, 
-				self.version_req, 
-				self.source_type
-			).await;
-
-			
-			let new_source_cache: HashMap<PathBuf, HashMap<Version, PackageSourceContent>> = save_psc_into_cache(package_source_content.clone(), source_cache);
-
-			return (new_source_cache, package_source_content.compile(namespace_name, self.inner_path));
+		let package_source_content: PackageSourceContent = match content_option {
+			Some(package_source_content) => package_source_content,
+			None if offline => return Err(MpmError::NotCached {
+				source_url: get_cache_key(&self.source_url, &self.asset).to_string_lossy().to_string(),
+				version_req: self.version_req.to_string(),
+			}),
+			None => {
+				let package_source_content: PackageSourceContent = PackageSourceContent::new(
+					self.source_url, 
+					self.version_req, 
+					self.source_type,
+					self.asset,
+					dependency_name,
+					myth_path,
+					&source_cache.user_config
+				).await?;
+				save_psc_into_cache(package_source_content.clone(), source_cache);
+				package_source_content
+			},
+		};
+
+		let compiled_key: String = package_source_content.get_compiled_key(&self.inner_path, &namespace_name, compile_options, &self.features);
+		if let Some(compiled_source) = source_cache.cache_path.as_deref().and_then(|cache_path| load_compiled(cache_path, &compiled_key)) {
+			tracing::debug!(dependency = dependency_name, version = package_source_content.version.to_string(), "{} is unchanged since it was last compiled", dependency_name);
+			return Ok(compiled_source);
+		}
+		let extracted_tree: &mut ExtractedTree = source_cache.get_tree(&package_source_content)?;
+		let compiled_source: CompiledSource = package_source_content.compile(extracted_tree, dependency_name, namespace_name, self.inner_path, compile_options, &self.features)?;
+		if let Some(cache_path) = source_cache.cache_path.as_deref().filter(|_| !source_cache.is_read_only) {
+			save_compiled(cache_path, &compiled_key, &compiled_source)?;
 		}
+		return Ok(compiled_source);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Write;
+	use zip::write::{FileOptions, ZipWriter};
+
+	const LIMITS: ArchiveLimits = ArchiveLimits {
+		max_total_bytes: 16,
+		max_files: 4,
+		max_file_bytes: 10,
+	};
+
+	/// Zips `files` into a zipball's single top folder
+	fn build_zip(files: &[(&str, &[u8])]) -> bytes::Bytes {
+		let mut writer: ZipWriter<io::Cursor<Vec<u8>>> = ZipWriter::new(io::Cursor::new(Vec::new()));
+		let options: FileOptions = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+		for (name, contents) in files {
+			writer.start_file(*name, options).expect("the entry starts");
+			writer.write_all(contents).expect("the entry is written");
+		}
+		return bytes::Bytes::from(writer.finish().expect("the zip is finished").into_inner());
+	}
+
+	fn open(data: &bytes::Bytes) -> Result<ExtractedTree> {
+		return ExtractedTree::open(data, blake3::hash(data), "test", false, LIMITS, None, true);
+	}
+
+	fn unwrap_violation(result: Result<impl std::fmt::Debug>) -> ArchiveViolation {
+		match result {
+			Err(MpmError::UnsafeArchive { violation, .. }) => return violation,
+			other => panic!("expected the archive to be refused, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn reads_files_below_the_top_folder() {
+		let data: bytes::Bytes = build_zip(&[("repo-abc/src/A.cs", b"class A {}")]);
+		let mut extracted_tree: ExtractedTree = open(&data).expect("the archive opens");
+		assert_eq!(extracted_tree.read_text("src/A.cs", "test").expect("the file reads"), "class A {}");
+		assert!(extracted_tree.dir_keys.contains("src"));
+	}
 
+	#[test]
+	fn refuses_symlinks() {
+		let mut writer: ZipWriter<io::Cursor<Vec<u8>>> = ZipWriter::new(io::Cursor::new(Vec::new()));
+		writer.add_symlink("repo-abc/link", "/etc/passwd", FileOptions::default()).expect("the symlink is added");
+		let data: bytes::Bytes = bytes::Bytes::from(writer.finish().expect("the zip is finished").into_inner());
+		assert!(matches!(unwrap_violation(open(&data)), ArchiveViolation::Symlink { .. }));
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn refuses_special_files() {
+		assert!(matches!(check_entry_type("dev", Some(0o020644)), Err(ArchiveViolation::SpecialFile { .. })));
+		assert!(check_entry_type("file", Some(0o100644)).is_ok());
+		assert!(check_entry_type("windows", Some(0o644)).is_ok());
+		assert!(check_entry_type("unknown", None).is_ok());
+	}
+
+	#[test]
+	fn refuses_paths_leading_outside() {
+		let data: bytes::Bytes = build_zip(&[("repo-abc/../../evil.cs", b"x")]);
+		assert!(matches!(unwrap_violation(open(&data)), ArchiveViolation::UnsafePath { .. }));
+		let data: bytes::Bytes = build_zip(&[("/etc/evil.cs", b"x")]);
+		assert!(matches!(unwrap_violation(open(&data)), ArchiveViolation::UnsafePath { .. }));
+	}
+
+	#[test]
+	fn refuses_too_many_files() {
+		let data: bytes::Bytes = build_zip(&[("r/a", b""), ("r/b", b""), ("r/c", b""), ("r/d", b""), ("r/e", b"")]);
+		assert!(matches!(unwrap_violation(open(&data)), ArchiveViolation::TooManyFiles { limit: 4 }));
+	}
+
+	#[test]
+	fn refuses_a_file_over_the_limit() {
+		let data: bytes::Bytes = build_zip(&[("r/fits.cs", b"0123456789"), ("r/big.cs", b"0123456789a")]);
+		let mut extracted_tree: ExtractedTree = open(&data).expect("the archive opens");
+		let violation: ArchiveViolation = unwrap_violation(extracted_tree.read_file("big.cs", "test"));
+		assert!(matches!(violation, ArchiveViolation::FileTooLarge { limit: 10, .. }));
+		assert!(extracted_tree.read_file("fits.cs", "test").is_ok());
+	}
+
+	#[test]
+	fn refuses_files_over_the_total_limit() {
+		let data: bytes::Bytes = build_zip(&[("r/a.cs", b"0123456789"), ("r/b.cs", b"0123456789")]);
+		let mut extracted_tree: ExtractedTree = open(&data).expect("the archive opens");
+		assert!(extracted_tree.read_file("a.cs", "test").is_ok());
+		let violation: ArchiveViolation = unwrap_violation(extracted_tree.read_file("b.cs", "test"));
+		assert!(matches!(violation, ArchiveViolation::TooLarge { limit: 16 }));
+	}
+
+	#[test]
+	fn finds_the_single_top_folder() {
+		let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<String>>();
+		let file_keys: Vec<String> = keys(&["repo/a.cs", "repo/src/b.cs"]);
+		let dir_keys: Vec<String> = keys(&["repo", "repo/src"]);
+		assert_eq!(find_top_folder(&file_keys.iter().collect::<Vec<&String>>(), &dir_keys.iter().collect::<Vec<&String>>()), Some(String::from("repo")));
+
+		let file_keys: Vec<String> = keys(&["repo/a.cs", "other/b.cs"]);
+		assert_eq!(find_top_folder(&file_keys.iter().collect::<Vec<&String>>(), &[]), None);
+
+		// a file beside the folder means there's no single top folder
+		let file_keys: Vec<String> = keys(&["repo/a.cs", "README.md"]);
+		assert_eq!(find_top_folder(&file_keys.iter().collect::<Vec<&String>>(), &[]), None);
+	}
+
+	#[test]
+	fn compiled_key_changes_with_what_the_script_depends_on() {
+		let content: PackageSourceContent = PackageSourceContent {
+			data: build_zip(&[("r/a.cs", b"class A {}")]),
+			version: Version::new(0, 2, 0),
+			source_url: PathBuf::from("https://github.com/owner/repo"),
+			asset: None,
+		};
+		let options: CompileOptions = CompileOptions::default();
+		let key: String = content.get_compiled_key("src", "Signal", &options, &[]);
+		assert_eq!(key, content.get_compiled_key("src", "Signal", &options, &[]));
+
+		let defined: CompileOptions = CompileOptions {
+			defines: vec![String::from("UI_TWEEN")],
+			..CompileOptions::default()
+		};
+		assert_ne!(key, content.get_compiled_key("src", "Signal", &defined, &[]));
+		assert_ne!(key, content.get_compiled_key("src", "MythA.Signal", &options, &[]));
+		assert_ne!(key, content.get_compiled_key("src/other", "Signal", &options, &[]));
+		assert_ne!(key, content.get_compiled_key("src", "Signal", &options, &[String::from("tween")]));
+	}
+
+	#[test]
+	fn zipballs_need_a_top_folder() {
+		let data: bytes::Bytes = build_zip(&[("a.cs", b""), ("b.cs", b"")]);
+		assert!(matches!(open(&data), Err(MpmError::Archive { .. })));
+		assert!(ExtractedTree::open(&data, blake3::hash(&data), "test", true, LIMITS, None, true).is_ok());
+	}
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::fs;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::Deserialize;
+use super::workspace::Workspace;
+use super::error::{MpmError, Result};
+
+pub const USER_DIR_NAME: &str = ".mpm";
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+/// Points mpm at a different user config, handy for tests against a local stand-in server
+pub const CONFIG_PATH_ENV_VAR: &str = "MPM_CONFIG";
+
+pub const DEFAULT_HOST: &str = "github.com";
+pub const DEFAULT_WEB_URL: &str = "https://github.com";
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostConfig {
+	/// Root of the REST API, e.g. `https://github.example.com/api/v3`
+	pub api_url: String,
+	/// Prefix that dependency URLs on this host start with, defaults to `https://<host>`
+	pub web_url: Option<String>,
+	/// Token used for this host instead of the global one
+	pub token: Option<String>,
+}
+
+/// How much a downloaded archive may unpack to before it's refused, so a broken or hostile release can't fill the disk
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ArchiveLimits {
+	/// Uncompressed size of every file together
+	pub max_total_bytes: u64,
+	pub max_files: usize,
+	/// Uncompressed size of any one file
+	pub max_file_bytes: u64,
+}
+
+impl Default for ArchiveLimits {
+	fn default() -> Self {
+		return ArchiveLimits {
+			max_total_bytes: 512 * 1024 * 1024,
+			max_files: 20_000,
+			max_file_bytes: 64 * 1024 * 1024,
+		};
+	}
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct UserConfig {
+	#[serde(default)]
+	pub hosts: HashMap<String, HostConfig>,
+	#[serde(default)]
+	pub archive: ArchiveLimits,
+}
+
+/// The `~/.mpm` directory holding user level config and credentials
+pub fn get_user_dir_path() -> Option<PathBuf> {
+	return dirs::home_dir().map(|home| home.join(USER_DIR_NAME));
+}
+
+pub fn get_config_path() -> Option<PathBuf> {
+	if let Ok(config_path) = env::var(CONFIG_PATH_ENV_VAR) {
+		return Some(PathBuf::from(config_path));
+	}
+	return get_user_dir_path().map(|dir| dir.join(CONFIG_FILE_NAME));
+}
+
+impl UserConfig {
+	/// Loads the user config, a missing file is the same as an empty one.
+	/// Hosts listed in the `mpm.toml` of `workspace` are added to it
+	pub fn load(workspace: &Workspace) -> Result<Self> {
+		let mut user_config: UserConfig = match get_config_path() {
+			Some(config_path) if config_path.exists() => {
+				let contents: String = fs::read_to_string(&config_path)
+					.map_err(|e| MpmError::io("read", &config_path, e))?;
+				toml::from_str(&contents).map_err(|e| MpmError::UserConfig {
+					path: config_path.clone(),
+					message: e.message().to_string(),
+				})?
+			},
+			_ => UserConfig::default(),
+		};
+		// the user's own entry for a host wins, it's where their token lives
+		for (host, host_config) in &workspace.config.hosts {
+			user_config.hosts.entry(host.clone()).or_insert(host_config.clone());
+		}
+		return Ok(user_config);
+	}
+
+	/// Finds the config for a host (`host` or `host:port`), github.com is always known
+	pub fn get_host(self: &Self, host: &str) -> Option<HostConfig> {
+		if let Some(host_config) = self.hosts.get(host) {
+			let mut host_config: HostConfig = host_config.clone();
+			if host_config.web_url.is_none() {
+				host_config.web_url = Some(format!("https://{}", host));
+			}
+			return Some(host_config);
+		}
+		if host == DEFAULT_HOST {
+			return Some(HostConfig {
+				api_url: DEFAULT_API_URL.to_string(),
+				web_url: Some(DEFAULT_WEB_URL.to_string()),
+				token: None,
+			});
+		}
+		return None;
+	}
+}
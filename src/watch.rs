@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use super::error::{MpmError, Result};
+
+/// How long the files have to stay untouched before a rebuild, editors often save in several steps
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// A directory to watch, optionally narrowed down to some file names in it
+#[derive(Debug, Clone)]
+pub struct WatchTarget {
+	pub dir_path: PathBuf,
+	pub recursive: bool,
+	/// Only changes to files with these names count, any change does when empty
+	pub file_names: Vec<String>,
+}
+
+impl WatchTarget {
+	fn matches(self: &Self, path: &Path) -> bool {
+		if !path.starts_with(&self.dir_path) {
+			return false;
+		}
+		if !self.recursive && path.parent() != Some(self.dir_path.as_path()) && path != self.dir_path {
+			return false;
+		}
+		if self.file_names.is_empty() {
+			return true;
+		}
+		return path.file_name()
+			.and_then(|n| n.to_str())
+			.map(|n| self.file_names.iter().any(|file_name| file_name == n))
+			.unwrap_or(false);
+	}
+}
+
+/// Paths are compared with what the OS reports, which is always resolved
+fn normalize(path: &Path) -> PathBuf {
+	if let Ok(canonical_path) = path.canonicalize() {
+		return canonical_path;
+	}
+	// files that don't exist yet, like an output before the first build, can still have their directory resolved
+	match (path.parent().and_then(|parent| parent.canonicalize().ok()), path.file_name()) {
+		(Some(parent), Some(file_name)) => return parent.join(file_name),
+		_ => return path.to_path_buf(),
+	}
+}
+
+pub struct ChangeWatcher {
+	watcher: RecommendedWatcher,
+	receiver: Receiver<notify::Result<Event>>,
+	watched_dirs: Vec<PathBuf>,
+	targets: Vec<WatchTarget>,
+	ignored_paths: Vec<PathBuf>,
+}
+
+impl ChangeWatcher {
+	pub fn new() -> Result<Self> {
+		let (sender, receiver) = channel();
+		let watcher: RecommendedWatcher = notify::recommended_watcher(sender).map_err(|e| MpmError::Watch {
+			path: PathBuf::from("."),
+			message: e.to_string(),
+		})?;
+		return Ok(ChangeWatcher {
+			watcher,
+			receiver,
+			watched_dirs: Vec::new(),
+			targets: Vec::new(),
+			ignored_paths: Vec::new(),
+		});
+	}
+
+	/// Replaces what's watched. Changes to `ignored_paths`, usually the files the rebuild writes, never count
+	pub fn set_targets(self: &mut Self, targets: Vec<WatchTarget>, ignored_paths: Vec<PathBuf>) -> Result<()> {
+		for dir_path in self.watched_dirs.drain(..) {
+			// the directory may be gone, which is fine
+			let _ = self.watcher.unwatch(&dir_path);
+		}
+
+		self.targets = targets.into_iter()
+			.filter(|target| target.dir_path.is_dir())
+			.map(|target| WatchTarget {
+				dir_path: normalize(&target.dir_path),
+				..target
+			})
+			.collect();
+		self.ignored_paths = ignored_paths.iter().map(|path| normalize(path)).collect();
+
+		// a directory is only watched once, recursively if any target needs it to be
+		let mut dirs: Vec<(PathBuf, bool)> = Vec::new();
+		for target in &self.targets {
+			match dirs.iter_mut().find(|(dir_path, _)| dir_path == &target.dir_path) {
+				Some((_, recursive)) => *recursive = *recursive || target.recursive,
+				None => dirs.push((target.dir_path.clone(), target.recursive)),
+			}
+		}
+		for (dir_path, recursive) in dirs {
+			let mode: RecursiveMode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+			self.watcher.watch(&dir_path, mode).map_err(|e| MpmError::Watch {
+				path: dir_path.clone(),
+				message: e.to_string(),
+			})?;
+			self.watched_dirs.push(dir_path);
+		}
+		return Ok(());
+	}
+
+	fn get_relevant_paths(self: &Self, event: Event) -> Vec<PathBuf> {
+		if matches!(event.kind, EventKind::Access(_)) {
+			return Vec::new();
+		}
+		return event.paths.into_iter()
+			.filter(|path| !self.ignored_paths.contains(path))
+			.filter(|path| self.targets.iter().any(|target| target.matches(path)))
+			.collect();
+	}
+
+	/// Blocks until a watched file changes and things have been quiet for `DEBOUNCE`, returning every path that changed
+	pub fn wait_for_change(self: &Self) -> Result<Vec<PathBuf>> {
+		let watch_error = |message: String| MpmError::Watch {
+			path: self.watched_dirs.first().cloned().unwrap_or_default(),
+			message,
+		};
+		let mut changed_paths: Vec<PathBuf> = Vec::new();
+		loop {
+			let event_result: notify::Result<Event> = match changed_paths.is_empty() {
+				true => self.receiver.recv().map_err(|e| watch_error(e.to_string()))?,
+				false => match self.receiver.recv_timeout(DEBOUNCE) {
+					Ok(event_result) => event_result,
+					Err(RecvTimeoutError::Timeout) => return Ok(changed_paths),
+					Err(RecvTimeoutError::Disconnected) => return Err(watch_error(String::from("the watcher stopped"))),
+				},
+			};
+			let event: Event = event_result.map_err(|e| watch_error(e.to_string()))?;
+			for path in self.get_relevant_paths(event) {
+				if !changed_paths.contains(&path) {
+					changed_paths.push(path);
+				}
+			}
+		}
+	}
+}
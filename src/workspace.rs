@@ -0,0 +1,140 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use semver::VersionReq;
+use serde::Deserialize;
+use super::cache::CACHE_DIR_NAME;
+use super::csharp_parse::CompileOptions;
+use super::collect::{validate_glob, validate_define};
+use super::user_config::{HostConfig, DEFAULT_HOST};
+use super::error::{MpmError, Result};
+
+/// Optional file at the root of a map holding defaults for every myth in it
+pub const WORKSPACE_FILE_NAME: &str = "mpm.toml";
+/// Each dependency's namespace is its name unless the workspace or myth says otherwise
+pub const DEFAULT_NAMESPACE_TEMPLATE: &str = "{name}";
+pub const NAMESPACE_PLACEHOLDERS: [&str; 2] = ["name", "myth"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+	/// Template for the namespace each dependency is compiled into, with `{name}` and `{myth}` filled in
+	pub namespace: Option<String>,
+	/// Relative to the workspace root
+	pub cache_dir: Option<PathBuf>,
+	/// Hosts every member of the map needs, the user config still wins for hosts it also lists.
+	/// github.com can't be among them, the user's global token goes wherever it points
+	pub hosts: HashMap<String, HostConfig>,
+	/// Globs of directories, relative to the workspace root, that are never searched for myths on top of the default ones
+	pub exclude: Vec<String>,
+	/// Whether searching for myths goes through symlinked directories, loops are skipped either way
+	pub follow_symlinks: bool,
+	/// Version requirements by dependency name, replacing the one in every myth's URL so the whole map agrees
+	pub overrides: HashMap<String, VersionReq>,
+	pub compiler: CompileOptions,
+}
+
+/// The map mpm is run in, found by looking for `mpm.toml` from the current directory up
+#[derive(Debug, Clone)]
+pub struct Workspace {
+	/// Where `mpm.toml` is, or the starting directory when there isn't one
+	pub root_path: PathBuf,
+	pub config: WorkspaceConfig,
+}
+
+/// Checks a namespace template, the error being why it can't be used
+pub fn validate_namespace_template(template: &str) -> std::result::Result<(), String> {
+	let placeholder_pattern: Regex = Regex::new(r"\{([^}]*)\}").expect("the pattern is valid");
+	for captures in placeholder_pattern.captures_iter(template) {
+		let placeholder: &str = captures.get(1).map(|m| m.as_str()).unwrap_or_default();
+		if !NAMESPACE_PLACEHOLDERS.contains(&placeholder) {
+			return Err(format!("`{{{}}}` isn't a placeholder, expected `{{name}}` or `{{myth}}`", placeholder));
+		}
+	}
+	if template.trim().is_empty() {
+		return Err(String::from("it's empty"));
+	}
+	return Ok(());
+}
+
+/// Fills in a namespace template for one dependency of a myth
+pub fn render_namespace(template: &str, myth: &str, name: &str) -> String {
+	return template.replace("{name}", name).replace("{myth}", myth);
+}
+
+impl Workspace {
+	/// Loads the nearest `mpm.toml` at or above `start_dir`, without one every setting keeps its default
+	pub fn find(start_dir: &Path) -> Result<Self> {
+		for dir_path in start_dir.ancestors() {
+			let config_path: PathBuf = dir_path.join(WORKSPACE_FILE_NAME);
+			if config_path.is_file() {
+				return Ok(Workspace {
+					root_path: dir_path.to_path_buf(),
+					config: Workspace::load_config(&config_path)?,
+				});
+			}
+		}
+		return Ok(Workspace {
+			root_path: start_dir.to_path_buf(),
+			config: WorkspaceConfig::default(),
+		});
+	}
+
+	fn load_config(config_path: &Path) -> Result<WorkspaceConfig> {
+		let contents: String = fs::read_to_string(config_path).map_err(|e| MpmError::io("read", config_path, e))?;
+		let invalid = |message: String| MpmError::WorkspaceConfig {
+			path: config_path.to_path_buf(),
+			message,
+		};
+		let config: WorkspaceConfig = toml::from_str(&contents).map_err(|e| invalid(e.message().to_string()))?;
+		if let Some(template) = &config.namespace {
+			validate_namespace_template(template).map_err(|reason| invalid(format!("`namespace` can't be used, {}", reason)))?;
+		}
+		// a cloned map mustn't be able to send the user's token somewhere else
+		if config.hosts.contains_key(DEFAULT_HOST) {
+			return Err(invalid(format!("`hosts.\"{}\"` can only be set in the user config", DEFAULT_HOST)));
+		}
+		for symbol in &config.compiler.defines {
+			validate_define(symbol).map_err(|reason| invalid(format!("`{}` can't be defined, {}", symbol, reason)))?;
+		}
+		for pattern in &config.exclude {
+			validate_glob(pattern).map_err(|reason| invalid(format!("`{}` is not a valid glob: {}", pattern, reason)))?;
+		}
+		return Ok(config);
+	}
+
+	pub fn get_cache_path(self: &Self) -> PathBuf {
+		return self.root_path.join(self.config.cache_dir.clone().unwrap_or(PathBuf::from(CACHE_DIR_NAME)));
+	}
+
+	pub fn get_namespace_template(self: &Self) -> &str {
+		return self.config.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE_TEMPLATE);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn load(contents: &str) -> Result<Workspace> {
+		let temp_dir: tempfile::TempDir = tempfile::tempdir().expect("the temp dir is created");
+		fs::write(temp_dir.path().join(WORKSPACE_FILE_NAME), contents).expect("the workspace config is written");
+		return Workspace::find(temp_dir.path());
+	}
+
+	#[test]
+	fn refuses_github_among_its_hosts() {
+		let result: Result<Workspace> = load("[hosts.\"github.com\"]\napi_url = \"https://evil.example.com/api\"\n");
+		match result {
+			Err(MpmError::WorkspaceConfig { message, .. }) => assert!(message.contains("github.com"), "{}", message),
+			other => panic!("expected github.com to be refused, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn keeps_other_hosts() {
+		let workspace: Workspace = load("[hosts.\"ghe.example.com\"]\napi_url = \"https://ghe.example.com/api/v3\"\n").expect("the workspace loads");
+		assert_eq!(workspace.config.hosts["ghe.example.com"].api_url, "https://ghe.example.com/api/v3");
+	}
+}